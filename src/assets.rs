@@ -5,6 +5,8 @@ use std::path::PathBuf;
 use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
 
+use crate::chess::OpeningBook;
+
 #[cfg(not(feature = "cargo"))]
 lazy_static::lazy_static! {
     static ref RUNFILES: runfiles::Runfiles = {
@@ -72,6 +74,7 @@ pub fn asset_path(path: &str) -> PathBuf {
 pub struct AssetLibrary {
     pub scenes: HashMap<String, Handle<Gltf>>,
     pub materials: HashMap<String, Handle<StandardMaterial>>,
+    pub books: HashMap<String, Handle<OpeningBook>>,
 }
 
 impl AssetLibrary {
@@ -87,16 +90,16 @@ impl AssetLibrary {
         self.scenes.insert(id, asset);
     }
 
-    pub fn get_material(&self, id: &String) -> Option<&Handle<StandardMaterial>> {
-        self.materials.get(id)
+    pub fn get_book(&self, id: &str) -> Option<&Handle<OpeningBook>> {
+        self.books.get(id)
     }
 
-    pub fn insert_material(&mut self, id: String, asset: Handle<StandardMaterial>) {
-        if self.materials.contains_key(&id) {
+    pub fn insert_book(&mut self, id: String, asset: Handle<OpeningBook>) {
+        if self.books.contains_key(&id) {
             panic!("Double inserted asset: {}", id);
         }
 
-        self.materials.insert(id, asset);
+        self.books.insert(id, asset);
     }
 
     pub fn is_all_assets_loaded(&self, asset_server: &Res<AssetServer>) -> bool {
@@ -112,6 +115,17 @@ impl AssetLibrary {
             }
         }
 
+        // Books are optional so the game goes on without one that fails to
+        // load.
+        for book in self.books.values() {
+            if asset_server
+                .get_load_state(book)
+                .is_some_and(|state| state.is_loading())
+            {
+                return false;
+            }
+        }
+
         true
     }
 }
@@ -14,7 +14,57 @@ use crate::AppState;
 
 mod board_coords;
 use board_coords::BoardCoordinate as Coord;
-use board_coords::Direction as BoardDir;
+
+mod position;
+pub use position::{GameOverReason, GameResult, Move, Piece, Position};
+
+mod promotion;
+use promotion::PendingPromotion;
+
+mod history;
+pub use history::GameHistory;
+use history::MovedFlags;
+
+mod pgn;
+
+mod replay;
+pub use replay::Replay;
+
+mod undo;
+use undo::TakeBack;
+pub use undo::{RedoMove, UndoMove};
+
+mod engine;
+pub use engine::Difficulty;
+
+mod ai;
+pub use ai::ComputerPlayer;
+
+mod uci;
+pub use uci::run_uci;
+
+mod xboard;
+pub use xboard::run_xboard;
+
+mod external;
+pub use external::ExternalEngine;
+
+mod book;
+pub use book::OpeningBook;
+
+mod markers;
+use book::{BookExplorer, OpeningBookLoader, BOOK};
+use markers::{MarkerAssets, MoveMarker};
+
+mod drag;
+use drag::Dragging;
+
+mod animation;
+pub use animation::AnimationSettings;
+use animation::PieceTween;
+
+mod hud;
+use hud::{HistoryView, Hud};
 
 #[derive(
     Debug,
@@ -27,6 +77,7 @@ use board_coords::Direction as BoardDir;
     Ord,
     Hash,
     Clone,
+    Copy,
 )]
 pub enum ChessPieceType {
     Pawn,
@@ -37,7 +88,7 @@ pub enum ChessPieceType {
     King,
 }
 
-#[derive(Debug, Component, Clone, Copy, strum_macros::EnumIter, Eq, PartialEq)]
+#[derive(Debug, Component, Clone, Copy, strum_macros::EnumIter, Eq, PartialEq, Hash)]
 pub enum Team {
     Black,
     White,
@@ -46,8 +97,8 @@ pub enum Team {
 impl std::fmt::Display for Team {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Team::Black => write!(f, "Team::Black"),
-            Team::White => write!(f, "Team::White"),
+            Team::Black => write!(f, "Black"),
+            Team::White => write!(f, "White"),
         }
     }
 }
@@ -61,11 +112,58 @@ impl Default for ActiveTeam {
     }
 }
 
-#[derive(Debug, Event)]
+/// How new games are set up, as chosen on the new game screen.
+#[derive(Resource, Default)]
+pub struct GameConfig {
+    /// The side the computer plays, or `None` when two people play.
+    pub computer: Option<Team>,
+    pub difficulty: Difficulty,
+
+    /// A UCI engine to run in place of the built-in one. When nobody plays
+    /// the computer it analyses the game instead.
+    pub engine: Option<String>,
+
+    /// A Polyglot opening book for the computer to play from and the
+    /// explorer panel to show.
+    pub book: Option<String>,
+}
+
+/// The position new games are set up from.
+#[derive(Resource, Default)]
+pub struct StartingPosition(pub Position);
+
+#[derive(Debug, Event, Clone)]
 pub struct PieceMoveEvent {
     pub board: Entity,
     pub from: Coord,
     pub to: Coord,
+
+    /// The piece a pawn reaching the far rank becomes. When a promotion is
+    /// required but missing, the player is asked to choose one.
+    pub promotion: Option<ChessPieceType>,
+}
+
+impl PieceMoveEvent {
+    pub fn as_move(&self) -> Move {
+        Move {
+            from: self.from,
+            to: self.to,
+            promotion: self.promotion,
+        }
+    }
+}
+
+impl std::fmt::Display for PieceMoveEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_move())
+    }
+}
+
+/// Sent once a game has finished, along with the switch to [AppState::GameOver].
+#[derive(Debug, Event, Clone)]
+pub struct GameOver {
+    pub result: GameResult,
+    pub reason: GameOverReason,
 }
 
 #[derive(Resource, Default)]
@@ -81,10 +179,18 @@ impl PieceSelection {
         active_team: Res<ActiveTeam>,
         mut selection: ResMut<PieceSelection>,
         mut writer: EventWriter<PieceMoveEvent>,
+        config: Res<GameConfig>,
     ) {
         trigger.propagate(false);
+        if config.computer == Some(active_team.0) {
+            println!("Waiting for the computer to move.");
+            return;
+        }
         let piece_entity = trigger.target();
-        let (piece, piece_relationship) = pieces_query.get(piece_entity).unwrap();
+        // Captured pieces are no longer pieces while they disappear.
+        let Ok((piece, piece_relationship)) = pieces_query.get(piece_entity) else {
+            return;
+        };
 
         match selection.piece {
             Some(selected) => {
@@ -110,8 +216,9 @@ impl PieceSelection {
                             board: board_entity,
                             from: selected_pos,
                             to: piece_pos,
+                            promotion: None,
                         };
-                        println!("Submitting movement: {:?}", movement);
+                        println!("Submitting movement: {}", movement);
                         writer.write(movement);
                         selection.piece = None;
                     }
@@ -136,8 +243,13 @@ impl PieceSelection {
         active_team: Res<ActiveTeam>,
         mut selection: ResMut<PieceSelection>,
         mut writer: EventWriter<PieceMoveEvent>,
+        config: Res<GameConfig>,
     ) {
         trigger.propagate(false);
+        if config.computer == Some(active_team.0) {
+            println!("Waiting for the computer to move.");
+            return;
+        }
 
         // Locate the board
         let board_entity = trigger.target();
@@ -147,16 +259,8 @@ impl PieceSelection {
         let trigger_pos = trigger.event().hit.position.unwrap();
 
         // Find the closest point to the target
-        let coord = {
-            let mut distances = Coord::iter()
-                .map(|coord| {
-                    let cell_pos = board.get_cell_translation(&coord, &board_transform);
-                    (trigger_pos.distance(cell_pos), coord)
-                })
-                .collect::<Vec<(_, _)>>();
-
-            distances.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-            distances.first().unwrap().1
+        let Some(coord) = board.nearest_cell(trigger_pos, board_transform) else {
+            return;
         };
 
         match selection.piece {
@@ -166,8 +270,9 @@ impl PieceSelection {
                     board: board_entity,
                     from: board.occupants[&piece],
                     to: coord,
+                    promotion: None,
                 };
-                println!("Submitting movement: {:?}", movement);
+                println!("Submitting movement: {}", movement);
                 writer.write(movement);
                 selection.piece = None;
             }
@@ -246,6 +351,9 @@ impl ChessPiece {
         }
     }
 
+    /// Spawn a piece on `position`. `has_moved` says whether it has moved
+    /// earlier in the game, for pieces brought back by taking moves back.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         commands: &mut Commands,
         asset_library: &Res<AssetLibrary>,
@@ -255,6 +363,7 @@ impl ChessPiece {
         team: Team,
         position: Coord,
         kind: ChessPieceType,
+        has_moved: bool,
     ) -> Entity {
         let asset_id = kind.to_string();
 
@@ -271,15 +380,15 @@ impl ChessPiece {
         let scene = gltf.default_scene.clone().unwrap();
 
         // Locate the transform
-        let transform = board.get_cell_transform(&position, &board_transform, &team);
+        let transform = board.get_cell_transform(&position, board_transform, &team);
 
         // Spawn in world
         let entity = commands
             .spawn((
                 ChessPiece {
-                    kind: kind.clone(),
+                    kind,
                     team,
-                    has_moved: false,
+                    has_moved,
                 },
                 team,
                 transform,
@@ -287,6 +396,9 @@ impl ChessPiece {
                 PieceNeedsTeamMaterial,
             ))
             .observe(PieceSelection::observer_select_piece)
+            .observe(Dragging::observer_drag_start)
+            .observe(Dragging::observer_drag)
+            .observe(Dragging::observer_drag_end)
             .id();
 
         board.insert_piece(entity, position);
@@ -308,6 +420,9 @@ struct GridCell {
 pub struct ChessBoard {
     grid: Vec<Vec<GridCell>>,
     occupants: HashMap<Entity, Coord>,
+
+    /// The rules model the board's entities are a view of.
+    position: Position,
 }
 
 impl ChessBoard {
@@ -323,7 +438,7 @@ impl ChessBoard {
         asset_library: &Res<AssetLibrary>,
         gltf_assets: &Res<Assets<Gltf>>,
         gltf_node_assets: &Res<Assets<GltfNode>>,
-        with_pieces: bool,
+        position: &Position,
     ) {
         let asset_id = "BOARD".to_string();
         // Locate the board resource
@@ -346,51 +461,26 @@ impl ChessBoard {
         let mut board = ChessBoard {
             grid,
             occupants: HashMap::new(),
+            position: position.clone(),
         };
         let board_transform = Transform::from_xyz(0.0, 0.0, 0.0);
 
-        let mut pieces = Vec::new();
-        if with_pieces {
-            #[rustfmt::skip]
-            pieces.extend([
-
-                // Spawn white team
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::A2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::B2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::C2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::D2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::E2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::F2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::G2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::H2, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::A1, ChessPieceType::Rook),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::B1, ChessPieceType::Knight),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::C1, ChessPieceType::Bishop),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::D1, ChessPieceType::Queen),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::E1, ChessPieceType::King),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::F1, ChessPieceType::Bishop),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::G1, ChessPieceType::Knight),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::White, Coord::H1, ChessPieceType::Rook),
-
-                // Spawn black team.
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::A7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::B7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::C7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::D7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::E7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::F7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::G7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::H7, ChessPieceType::Pawn),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::A8, ChessPieceType::Rook),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::B8, ChessPieceType::Knight),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::C8, ChessPieceType::Bishop),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::D8, ChessPieceType::Queen),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::E8, ChessPieceType::King),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::F8, ChessPieceType::Bishop),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::G8, ChessPieceType::Knight),
-                ChessPiece::spawn(commands, &asset_library, &gltf_assets, &mut board, &board_transform, Team::Black, Coord::H8, ChessPieceType::Rook),
-            ]);
-        }
+        let pieces = position
+            .pieces()
+            .map(|(coord, piece)| {
+                ChessPiece::spawn(
+                    commands,
+                    asset_library,
+                    gltf_assets,
+                    &mut board,
+                    &board_transform,
+                    piece.team,
+                    coord,
+                    piece.kind,
+                    false,
+                )
+            })
+            .collect::<Vec<_>>();
 
         let board_entity = commands
             .spawn((
@@ -422,6 +512,25 @@ impl ChessBoard {
         board_transform.translation + (cell.translation * board_transform.scale)
     }
 
+    /// The distance between neighbouring cells.
+    pub fn cell_size(&self, board_transform: &Transform) -> f32 {
+        self.get_cell_translation(&Coord::A1, board_transform)
+            .distance(self.get_cell_translation(&Coord::A2, board_transform))
+    }
+
+    /// The cell closest to `point` on the board's surface, or `None` if it
+    /// isn't over the board.
+    pub fn nearest_cell(&self, point: Vec3, board_transform: &Transform) -> Option<Coord> {
+        let (distance, coord) = Coord::iter()
+            .map(|coord| {
+                let cell = self.get_cell_translation(&coord, board_transform);
+                (point.xz().distance(cell.xz()), coord)
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))?;
+
+        (distance <= self.cell_size(board_transform)).then_some(coord)
+    }
+
     pub fn get_cell_transform(
         &self,
         cell: &Coord,
@@ -441,10 +550,6 @@ impl ChessBoard {
             .with_rotation(rotation)
     }
 
-    pub fn is_cell_occupied(&self, cell: &Coord) -> bool {
-        self.get_cell(cell).occupant.is_some()
-    }
-
     pub fn insert_piece(&mut self, piece: Entity, position: Coord) {
         // Update the board grid to occupy the specified position.
         let cell = self.get_cell_mut(&position);
@@ -457,8 +562,8 @@ impl ChessBoard {
     pub fn move_piece(
         &mut self,
         piece_to_move: Entity,
+        piece: &mut ChessPiece,
         piece_transform: &mut Transform,
-        team: &Team,
         to: Coord,
         board_transform: &Transform,
     ) {
@@ -484,7 +589,8 @@ impl ChessBoard {
             from_cell.occupant = None;
         }
 
-        *piece_transform = self.get_cell_transform(&to, board_transform, team);
+        piece.has_moved = true;
+        *piece_transform = self.get_cell_transform(&to, board_transform, &piece.team);
     }
 
     pub fn remove_piece(&mut self, piece: Entity) {
@@ -496,6 +602,52 @@ impl ChessBoard {
         let cell = self.get_cell_mut(&position);
         cell.occupant = None;
     }
+
+    /// Jump the board to `position`, replacing only the pieces that differ.
+    #[allow(clippy::too_many_arguments)]
+    pub fn show_position(
+        &mut self,
+        board_entity: Entity,
+        position: &Position,
+        commands: &mut Commands,
+        pieces_query: &Query<&ChessPiece>,
+        asset_library: &Res<AssetLibrary>,
+        gltf_assets: &Res<Assets<Gltf>>,
+        board_transform: &Transform,
+    ) {
+        for coord in Coord::iter() {
+            let wanted = position.piece_at(coord);
+            if let Some(occupant) = self.get_cell(&coord).occupant {
+                let current = pieces_query
+                    .get(occupant)
+                    .ok()
+                    .map(|piece| Piece::new(piece.team, piece.kind));
+                if current == wanted {
+                    continue;
+                }
+
+                self.remove_piece(occupant);
+                commands.entity(occupant).despawn();
+            }
+
+            if let Some(piece) = wanted {
+                let entity = ChessPiece::spawn(
+                    commands,
+                    asset_library,
+                    gltf_assets,
+                    self,
+                    board_transform,
+                    piece.team,
+                    coord,
+                    piece.kind,
+                    false,
+                );
+                commands.entity(board_entity).add_child(entity);
+            }
+        }
+
+        self.position = position.clone();
+    }
 }
 
 const CAMERA_START_POSITION: Vec3 = Vec3 {
@@ -509,7 +661,12 @@ const CAMERA_FOCUS: Vec3 = Vec3::ZERO;
 struct Chess;
 
 impl Chess {
-    fn on_enter_loading(mut commands: Commands, asset_server: Res<AssetServer>) {
+    fn on_enter_loading(
+        mut commands: Commands,
+        asset_server: Res<AssetServer>,
+        starting_position: Res<StartingPosition>,
+        config: Res<GameConfig>,
+    ) {
         // Spawn Camera
         commands.spawn((
             Camera3d::default(),
@@ -538,19 +695,47 @@ impl Chess {
         // Chess Pierces
         ChessPiece::on_enter_loading(&asset_server, &mut asset_library);
 
+        // Opening book, from the source registered for its folder.
+        if let Some(path) = &config.book {
+            asset_library.insert_book(
+                BOOK.to_string(),
+                asset_server.load(OpeningBook::asset_path(path)),
+            );
+        }
+
         // Allocate any necessary resources.
         commands.insert_resource(asset_library);
-        commands.insert_resource(ActiveTeam(Team::White));
-        commands.insert_resource(PieceSelection::default())
+        commands.insert_resource(ActiveTeam(starting_position.0.side_to_move()));
+        commands.insert_resource(PieceSelection::default());
+        commands.insert_resource(PendingPromotion::default());
+        commands.insert_resource(GameHistory::new(starting_position.0.clone()));
+        commands.remove_resource::<ComputerPlayer>();
+        commands.remove_resource::<ExternalEngine>();
+        let external = config.engine.as_ref().and_then(|path| {
+            ExternalEngine::start(path, config.computer, config.difficulty)
+                .inspect_err(|e| println!("{}", e))
+                .ok()
+        });
+        match (external, config.computer) {
+            (Some(external), _) => commands.insert_resource(external),
+            // Fall back to the built-in engine when an external one fails.
+            (None, Some(team)) => {
+                commands.insert_resource(ComputerPlayer::new(team, config.difficulty))
+            }
+            (None, None) => {}
+        }
     }
 
     /// Check to see if all known assets have finished loading and we're ready to play the game
+    #[allow(clippy::too_many_arguments)]
     fn on_loading(
         mut commands: Commands,
         asset_server: Res<AssetServer>,
         asset_library: Res<AssetLibrary>,
         gltf_assets: Res<Assets<Gltf>>,
         gltf_node_assets: Res<Assets<GltfNode>>,
+        starting_position: Res<StartingPosition>,
+        books: Res<Assets<OpeningBook>>,
         mut next_state: ResMut<NextState<AppState>>,
     ) {
         // Wait for all assets to be fully loaded.
@@ -558,13 +743,20 @@ impl Chess {
             return;
         }
 
+        Hud::spawn(&mut commands);
+        if OpeningBook::from_library(&asset_library, &books).is_some() {
+            BookExplorer::spawn(&mut commands);
+        } else if asset_library.get_book(BOOK).is_some() {
+            println!("Failed to load the opening book, playing without it.");
+        }
+
         // Spawn board and all pieces
         ChessBoard::spawn(
             &mut commands,
             &asset_library,
             &gltf_assets,
             &gltf_node_assets,
-            true,
+            &starting_position.0,
         );
 
         // Trigger the next state.
@@ -639,277 +831,341 @@ impl Chess {
         camera.look_at(focus, camera_up);
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn update_move(
         mut move_events: EventReader<PieceMoveEvent>,
         mut commands: Commands,
-        mut pieces_query: Query<(&ChessPiece, &mut Transform), Without<ChessBoard>>,
+        mut pieces_query: Query<(&mut ChessPiece, &mut Transform), Without<ChessBoard>>,
         mut boards_query: Query<(&mut ChessBoard, &Transform), Without<ChessPiece>>,
         mut active_team: ResMut<ActiveTeam>,
+        mut pending_promotion: ResMut<PendingPromotion>,
+        asset_library: Res<AssetLibrary>,
+        gltf_assets: Res<Assets<Gltf>>,
+        mut game_over_writer: EventWriter<GameOver>,
+        mut next_state: ResMut<NextState<AppState>>,
+        mut history: ResMut<GameHistory>,
+        replay: Option<Res<Replay>>,
+        animation_settings: Res<AnimationSettings>,
+        view: Option<Res<HistoryView>>,
     ) {
         for event in move_events.read() {
-            println!("Handling move: {:?}", event);
+            println!("Handling move: {}", event);
+            if replay.is_some() {
+                println!("Moves cannot be made while replaying a game.");
+                continue;
+            }
+            if view.is_some() {
+                println!("Moves cannot be made while viewing an earlier move.");
+                continue;
+            }
+
+            if pending_promotion.is_pending() && event.promotion.is_none() {
+                println!("A promotion piece must be chosen first.");
+                continue;
+            }
+
             let (mut board, board_transform) = match boards_query.get_mut(event.board) {
                 Ok(b) => b,
                 Err(e) => panic!("Unable to find chess board: {:?}", e),
             };
 
             // Check if movement is legal
-            let moves = Chess::compute_moves(
-                &board,
-                pieces_query.transmute_lens::<&ChessPiece>().query(),
-                event.from,
-            );
+            let moves = Chess::compute_moves(&board, event.from);
             if !moves.contains(&event.to) {
-                println!(
-                    "Move is illegal! `{} -> {}`. Possible moves `{:?}`",
-                    event.from, event.to, moves
-                );
+                let team = board.position.side_to_move();
+                let is_king = board.position.king_square(team) == Some(event.from);
+                if is_king
+                    && board
+                        .position
+                        .attacked_squares(team.opponent())
+                        .contains(&event.to)
+                {
+                    println!(
+                        "Move is illegal! The king cannot move into check at {}",
+                        event.to
+                    );
+                } else {
+                    let possible = board
+                        .position
+                        .legal_moves_from(event.from)
+                        .iter()
+                        .map(|mv| board.position.to_san(mv))
+                        .collect::<Vec<_>>();
+                    println!(
+                        "Move is illegal! `{}`. Possible moves `{}`",
+                        event,
+                        possible.join(" ")
+                    );
+                }
                 continue;
             }
 
-            let from_cell = board.get_cell_mut(&event.from);
-            let from_occupant = match from_cell.occupant {
-                Some(o) => o,
-                None => panic!(
-                    "A move was made for a cell that has no occupant: {:?}",
-                    event
-                ),
-            };
+            let mv = event.as_move();
 
-            // Deleting any pieces that were taken
-            let to_cell = board.get_cell(&event.to);
-            if let Some(to_occupant) = to_cell.occupant {
-                board.remove_piece(to_occupant);
-                commands.entity(to_occupant).despawn();
+            // Ask which piece to promote to before going any further.
+            if board.position.is_promotion(mv.from, mv.to) && mv.promotion.is_none() {
+                pending_promotion.request(&mut commands, event);
+                continue;
             }
 
-            // Move piece from one square to another
-            let (from_piece, mut from_transform) = pieces_query
-                .get_mut(from_occupant)
-                .expect("Failed to get from piece");
+            if !board.position.legal_moves_from(mv.from).contains(&mv) {
+                println!("Move is illegal! Invalid promotion `{}`", mv);
+                continue;
+            }
 
-            board.move_piece(
-                from_occupant,
-                &mut from_transform,
-                &from_piece.team,
-                event.to,
-                &board_transform,
+            Chess::play_move(
+                &mut commands,
+                event.board,
+                &mut board,
+                board_transform,
+                &mut pieces_query,
+                &asset_library,
+                &gltf_assets,
+                &mut history,
+                &animation_settings,
+                mv,
             );
 
-            // Replacing any pawns that were upgraded.
-            if from_piece.kind == ChessPieceType::Pawn {
-                // TODO:
+            let is_over = Chess::finish_turn(
+                &board.position,
+                &mut active_team,
+                &mut history,
+                &mut game_over_writer,
+                &mut next_state,
+            );
+            if is_over {
+                // Ignore any further moves that were already queued.
+                break;
             }
-
-            // Toggle the active team
-            active_team.0 = match active_team.0 {
-                Team::Black => Team::White,
-                Team::White => Team::Black,
-            };
         }
     }
 
-    fn compute_moves(
-        board: &ChessBoard,
-        pieces: Query<&ChessPiece>,
-        from: Coord,
-    ) -> HashSet<Coord> {
-        let from_cell = board.get_cell(&from);
-        let piece_entity = from_cell
+    /// Apply a legal move to the board's entities and its rules model,
+    /// recording it in the history. The pieces are animated into place
+    /// unless animations are off.
+    #[allow(clippy::too_many_arguments)]
+    fn play_move(
+        commands: &mut Commands,
+        board_entity: Entity,
+        board: &mut ChessBoard,
+        board_transform: &Transform,
+        pieces_query: &mut Query<(&mut ChessPiece, &mut Transform), Without<ChessBoard>>,
+        asset_library: &Res<AssetLibrary>,
+        gltf_assets: &Res<Assets<Gltf>>,
+        history: &mut GameHistory,
+        animation_settings: &AnimationSettings,
+        mv: Move,
+    ) {
+        let from_occupant = board
+            .get_cell(&mv.from)
             .occupant
-            .expect("Cannot compute moves from a cell with no pieces");
-        let piece = pieces
-            .get(piece_entity)
-            .unwrap_or_else(|e| panic!("Piece {} was not in query\n{:?}", from, e));
-
-        let mut moves = HashSet::new();
-
-        let forward = match piece.team {
-            Team::Black => -1,
-            Team::White => 1,
+            .unwrap_or_else(|| panic!("A move was made for a cell that has no occupant: {}", mv));
+
+        let castling_side = board.position.castling_side(&mv);
+        let mut moved = MovedFlags::default();
+
+        // How long a piece takes to move between two squares.
+        let cell_size = board.cell_size(board_transform);
+        let move_seconds = |board: &ChessBoard, from: Coord, to: Coord| {
+            let distance = board
+                .get_cell_translation(&from, board_transform)
+                .distance(board.get_cell_translation(&to, board_transform));
+            animation_settings.move_seconds(distance / cell_size)
         };
-
-        match piece.kind {
-            ChessPieceType::Pawn => {
-                if let Ok(ahead) = from.try_transform(0, 1 * forward) {
-                    if !board.is_cell_occupied(&ahead) {
-                        moves.insert(ahead);
-
-                        if !piece.has_moved {
-                            let ahead = ahead.try_transform( 0, 1 * forward).expect("Pawns should always spawn with at least two sqpaces ahead of them.");
-                            if !board.is_cell_occupied(&ahead) {
-                                moves.insert(ahead);
-                            }
-                        }
-                    }
+        let arrival = move_seconds(board, mv.from, mv.to);
+
+        // Deleting any pieces that were taken. En passant captures a pawn
+        // behind the destination rather than on it.
+        let captured_square = board
+            .position
+            .en_passant_capture_square(&mv)
+            .unwrap_or(mv.to);
+        let captured_cell = board.get_cell(&captured_square);
+        if let Some(captured_occupant) = captured_cell.occupant {
+            board.remove_piece(captured_occupant);
+            match pieces_query.get(captured_occupant) {
+                // Captured pieces shrink away once the capturing piece arrives.
+                Ok((captured, captured_transform)) if animation_settings.enabled() => {
+                    moved.captured = captured.has_moved;
+                    let tween = PieceTween::capture(
+                        *captured_transform,
+                        animation_settings.capture_seconds(),
+                    )
+                    .after(arrival);
+                    commands
+                        .entity(captured_occupant)
+                        .remove::<ChessPiece>()
+                        .insert(tween);
                 }
-            }
-            ChessPieceType::Rook => {
-                for movable_dir in [
-                    BoardDir::Up,
-                    BoardDir::Down,
-                    BoardDir::Left,
-                    BoardDir::Right,
-                ] {
-                    let mut distance = 1;
-                    let (x, y) = movable_dir.as_coords();
-                    loop {
-                        if let Ok(ahead) = from.try_transform(x * distance, y * distance) {
-                            println!("Rook checking {}", ahead);
-                            let cell = board.get_cell(&ahead);
-                            match cell.occupant {
-                                Some(entity) => {
-                                    let occupant = pieces.get(entity).unwrap_or_else(|e| {
-                                        panic!("Occupant {} is not a know piece: {:?}", entity, e)
-                                    });
-                                    if occupant.team != piece.team {
-                                        moves.insert(ahead);
-                                    }
-                                    break;
-                                }
-                                None => {
-                                    moves.insert(ahead);
-                                }
-                            }
-
-                            // Increase distance for the next check.
-                            distance += 1;
-                            continue;
-                        }
-
-                        break;
-                    }
-                }
-            }
-            ChessPieceType::Knight => {
-                for movable_dir in [
-                    BoardDir::UpLeft,
-                    BoardDir::UpRight,
-                    BoardDir::DownLeft,
-                    BoardDir::DownRight,
-                ] {
-                    let (x, y) = movable_dir.as_coords();
-                    let (x, y) = match movable_dir {
-                        BoardDir::UpRight => (x, y + 1),
-                        BoardDir::DownRight => (x, y - 1),
-                        BoardDir::DownLeft => (x, y - 1),
-                        BoardDir::UpLeft => (x, y + 1),
-                        _ => unreachable!(),
-                    };
-
-                    if let Ok(ahead) = from.try_transform(x, y) {
-                        let cell = board.get_cell(&ahead);
-                        match cell.occupant {
-                            Some(entity) => {
-                                let occupant = pieces.get(entity).unwrap_or_else(|e| {
-                                    panic!("Occupant {} is not a know piece: {:?}", entity, e)
-                                });
-                                if occupant.team != piece.team {
-                                    moves.insert(ahead);
-                                    continue;
-                                }
-                            }
-                            None => {
-                                moves.insert(ahead);
-                            }
-                        }
-                    }
-                }
-            }
-            ChessPieceType::Bishop => {
-                for movable_dir in [
-                    BoardDir::UpLeft,
-                    BoardDir::UpRight,
-                    BoardDir::DownLeft,
-                    BoardDir::DownRight,
-                ] {
-                    let mut distance = 1;
-                    let (x, y) = movable_dir.as_coords();
-                    loop {
-                        if let Ok(ahead) = from.try_transform(x * distance, y * distance) {
-                            let cell = board.get_cell(&ahead);
-                            match cell.occupant {
-                                Some(entity) => {
-                                    let occupant = pieces.get(entity).unwrap_or_else(|e| {
-                                        panic!("Occupant {} is not a know piece: {:?}", entity, e)
-                                    });
-                                    if occupant.team != piece.team {
-                                        moves.insert(ahead);
-                                        continue;
-                                    }
-                                }
-                                None => {
-                                    moves.insert(ahead);
-                                }
-                            }
-
-                            // Increase distance for the next check.
-                            distance += 1;
-                            continue;
-                        }
-
-                        break;
+                captured => {
+                    if let Ok((captured, _)) = captured {
+                        moved.captured = captured.has_moved;
                     }
+                    commands.entity(captured_occupant).despawn();
                 }
             }
-            ChessPieceType::Queen => {
-                for movable_dir in BoardDir::iter() {
-                    let mut distance = 1;
-                    let (x, y) = movable_dir.as_coords();
-                    loop {
-                        if let Ok(ahead) = from.try_transform(x * distance, y * distance) {
-                            let cell = board.get_cell(&ahead);
-                            match cell.occupant {
-                                Some(entity) => {
-                                    let occupant = pieces.get(entity).unwrap_or_else(|e| {
-                                        panic!("Occupant {} is not a know piece: {:?}", entity, e)
-                                    });
-                                    if occupant.team != piece.team {
-                                        moves.insert(ahead);
-                                        continue;
-                                    }
-                                }
-                                None => {
-                                    moves.insert(ahead);
-                                }
-                            }
-
-                            // Increase distance for the next check.
-                            distance += 1;
-                            continue;
-                        }
-
-                        break;
-                    }
+        }
+
+        // Move piece from one square to another
+        let (mut from_piece, mut from_transform) = pieces_query
+            .get_mut(from_occupant)
+            .expect("Failed to get from piece");
+        debug_assert_eq!(
+            board.position.piece_at(mv.from),
+            Some(Piece::new(from_piece.team, from_piece.kind)),
+            "The board is out of sync with its position."
+        );
+        debug_assert!(
+            castling_side.is_none() || !from_piece.has_moved,
+            "A king that has moved cannot castle."
+        );
+        moved.piece = from_piece.has_moved;
+
+        // Knights jump over the pieces in their way, the rest slide.
+        let start = *from_transform;
+        let is_knight = from_piece.kind == ChessPieceType::Knight;
+        board.move_piece(
+            from_occupant,
+            &mut from_piece,
+            &mut from_transform,
+            mv.to,
+            board_transform,
+        );
+        PieceTween::start(
+            commands,
+            animation_settings,
+            from_occupant,
+            &mut from_transform,
+            start,
+            |from, to| {
+                if is_knight {
+                    PieceTween::arc(from, to, arrival, cell_size)
+                } else {
+                    PieceTween::slide(from, to, arrival)
                 }
+            },
+        );
+        let from_team = from_piece.team;
+
+        // Replacing any pawns that were upgraded.
+        if let Some(kind) = mv.promotion {
+            board.remove_piece(from_occupant);
+            commands.entity(from_occupant).despawn();
+
+            let promoted = ChessPiece::spawn(
+                commands,
+                asset_library,
+                gltf_assets,
+                board,
+                board_transform,
+                from_team,
+                mv.to,
+                kind,
+                true,
+            );
+            commands.entity(board_entity).add_child(promoted);
+
+            // The new piece takes over the pawn's journey.
+            if animation_settings.enabled() {
+                let to = board.get_cell_transform(&mv.to, board_transform, &from_team);
+                commands
+                    .entity(promoted)
+                    .insert(PieceTween::slide(start, to, arrival));
             }
-            ChessPieceType::King => {
-                for movable_dir in BoardDir::iter() {
-                    let (x, y) = movable_dir.as_coords();
-                    if let Ok(ahead) = from.try_transform(x, y) {
-                        let cell = board.get_cell(&ahead);
-                        match cell.occupant {
-                            Some(entity) => {
-                                let occupant = pieces.get(entity).unwrap_or_else(|e| {
-                                    panic!("Occupant {} is not a know piece: {:?}", entity, e)
-                                });
-                                if occupant.team != piece.team {
-                                    moves.insert(ahead);
-                                    continue;
-                                }
-                            }
-                            None => {
-                                moves.insert(ahead);
-                            }
-                        }
-
-                        continue;
-                    }
-                }
+        }
+
+        // Castling moves the rook to the other side of the king.
+        if let Some(side) = castling_side {
+            let squares = side.squares(from_team);
+            let rook_entity = board
+                .get_cell(&squares.rook_from)
+                .occupant
+                .expect("Castling requires a rook.");
+            let (mut rook, mut rook_transform) = pieces_query
+                .get_mut(rook_entity)
+                .expect("Failed to get castling rook");
+            debug_assert!(!rook.has_moved, "A rook that has moved cannot castle.");
+
+            // The rook hops over the king once the king has arrived.
+            let rook_start = *rook_transform;
+            board.move_piece(
+                rook_entity,
+                &mut rook,
+                &mut rook_transform,
+                squares.rook_to,
+                board_transform,
+            );
+            let rook_seconds = move_seconds(board, squares.rook_from, squares.rook_to);
+            PieceTween::start(
+                commands,
+                animation_settings,
+                rook_entity,
+                &mut rook_transform,
+                rook_start,
+                |from, to| PieceTween::arc(from, to, rook_seconds, cell_size).after(arrival),
+            );
+        }
+
+        // Keep the rules model in step with the entities.
+        println!("Played {}", history.record(&board.position, mv, moved));
+        board.position.make_move(mv);
+    }
+
+    /// Hand the turn to the side to move in `position`, ending the game if it
+    /// is over. Returns whether the game ended.
+    fn finish_turn(
+        position: &Position,
+        active_team: &mut ActiveTeam,
+        history: &mut GameHistory,
+        game_over_writer: &mut EventWriter<GameOver>,
+        next_state: &mut NextState<AppState>,
+    ) -> bool {
+        active_team.0 = position.side_to_move();
+        if position.is_in_check(active_team.0) {
+            println!("{} is in check!", active_team.0);
+        }
+
+        match position.outcome() {
+            Some((result, reason)) => {
+                history.set_result(result);
+                game_over_writer.write(GameOver { result, reason });
+                next_state.set(AppState::GameOver);
+                true
             }
-        };
+            None => false,
+        }
+    }
+
+    /// Print the FEN of each board when "F" is pressed.
+    fn export_fen(
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        boards_query: Query<&ChessBoard>,
+        active_team: Res<ActiveTeam>,
+    ) {
+        if !keyboard_input.just_pressed(KeyCode::KeyF) {
+            return;
+        }
+
+        for board in &boards_query {
+            debug_assert_eq!(board.position.side_to_move(), active_team.0);
+            println!("FEN: {}", board.position.to_fen());
+        }
+    }
+
+    fn on_game_over(mut game_over_events: EventReader<GameOver>) {
+        for event in game_over_events.read() {
+            println!("Game over! {} by {}", event.result, event.reason);
+        }
+    }
 
-        moves
+    /// Compute the destinations the piece at `from` can legally move to.
+    fn compute_moves(board: &ChessBoard, from: Coord) -> HashSet<Coord> {
+        board
+            .position
+            .legal_moves_from(from)
+            .into_iter()
+            .map(|mv| mv.to)
+            .collect()
     }
 }
 pub struct ChessPlugin;
@@ -918,6 +1174,15 @@ impl Plugin for ChessPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(MeshPickingPlugin)
             .add_event::<PieceMoveEvent>()
+            .add_event::<GameOver>()
+            .add_event::<UndoMove>()
+            .add_event::<RedoMove>()
+            .init_asset::<OpeningBook>()
+            .init_asset_loader::<OpeningBookLoader>()
+            .init_resource::<MarkerAssets>()
+            .init_resource::<StartingPosition>()
+            .init_resource::<GameConfig>()
+            .init_resource::<AnimationSettings>()
             .add_systems(OnEnter(AppState::GameLoading), Chess::on_enter_loading)
             .add_systems(
                 Update,
@@ -926,9 +1191,42 @@ impl Plugin for ChessPlugin {
             .add_systems(
                 Update,
                 (
-                    Chess::update_camera.run_if(in_state(AppState::Game)),
+                    Chess::update_camera
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
                     Chess::update_move.run_if(in_state(AppState::Game)),
+                    ComputerPlayer::on_update
+                        .before(Chess::update_move)
+                        .run_if(in_state(AppState::Game).and(resource_exists::<ComputerPlayer>)),
+                    ExternalEngine::on_update.before(Chess::update_move).run_if(
+                        in_state(AppState::Game)
+                            .or(in_state(AppState::GameOver))
+                            .and(resource_exists::<ExternalEngine>),
+                    ),
+                    Chess::export_fen
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    GameHistory::on_save_pgn
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    PendingPromotion::on_update.run_if(in_state(AppState::Game)),
+                    TakeBack::on_keyboard
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    TakeBack::on_update
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    Replay::on_update
+                        .run_if(in_state(AppState::Game).and(resource_exists::<Replay>)),
+                    BookExplorer::on_update
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    MoveMarker::on_update
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    PieceTween::on_update,
+                    HistoryView::on_update
+                        .before(PendingPromotion::on_update)
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    (Hud::on_update, Hud::on_update_moves, Hud::on_scroll)
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
+                    AnimationSettings::on_keyboard
+                        .run_if(in_state(AppState::Game).or(in_state(AppState::GameOver))),
                     ChessPiece::on_spawn_scene,
+                    Chess::on_game_over,
                 ),
             );
     }
@@ -0,0 +1,139 @@
+//! Letting the engine play one side of the game.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy::tasks::{block_on, poll_once, AsyncComputeTaskPool, Task};
+
+use super::engine::{Difficulty, Engine, SearchLimits};
+use super::position::mix;
+use super::{
+    ActiveTeam, ChessBoard, HistoryView, Move, OpeningBook, PendingPromotion, PieceMoveEvent,
+    Replay, Team,
+};
+use crate::assets::AssetLibrary;
+
+/// The side the computer plays and how it searches for moves.
+#[derive(Resource)]
+pub struct ComputerPlayer {
+    pub team: Team,
+    pub limits: SearchLimits,
+
+    /// Shared with each search task so the engine's table lasts the game.
+    engine: Arc<Mutex<Engine>>,
+
+    thinking: Option<Thinking>,
+
+    /// Varies the book moves chosen from one game to the next.
+    seed: u64,
+}
+
+/// A search running in the background.
+struct Thinking {
+    /// The key of the position being searched, to notice if the board
+    /// changes meanwhile.
+    key: u64,
+    stop: Arc<AtomicBool>,
+    task: Task<Option<Move>>,
+}
+
+impl ComputerPlayer {
+    pub fn new(team: Team, difficulty: Difficulty) -> Self {
+        Self {
+            team,
+            limits: difficulty.limits(),
+            engine: Arc::new(Mutex::new(Engine::default())),
+            thinking: None,
+            seed: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64),
+        }
+    }
+
+    /// Start searching when it's the computer's turn and submit the move
+    /// once the search is done.
+    #[allow(clippy::too_many_arguments)]
+    pub fn on_update(
+        mut computer: ResMut<ComputerPlayer>,
+        board: Single<(Entity, &ChessBoard)>,
+        active_team: Res<ActiveTeam>,
+        pending_promotion: Res<PendingPromotion>,
+        replay: Option<Res<Replay>>,
+        view: Option<Res<HistoryView>>,
+        asset_library: Res<AssetLibrary>,
+        books: Res<Assets<OpeningBook>>,
+        mut writer: EventWriter<PieceMoveEvent>,
+    ) {
+        if replay.is_some() || view.is_some() {
+            return;
+        }
+
+        let (board_entity, board) = *board;
+        let key = board.position.hash_key();
+
+        // A take back leaves the search thread working on a position that is
+        // no longer on the board. Tell it to give up and let the result go.
+        if let Some(thinking) = &computer.thinking {
+            if thinking.key != key {
+                thinking.stop.store(true, Ordering::Relaxed);
+                computer.thinking = None;
+            }
+        }
+
+        if active_team.0 != computer.team || pending_promotion.is_pending() {
+            return;
+        }
+
+        // Play from the book for as long as it has moves for the position.
+        let book = OpeningBook::from_library(&asset_library, &books);
+        let roll = mix(key ^ computer.seed);
+        if let Some(mv) = book.and_then(|book| book.choose(&board.position, roll)) {
+            println!(
+                "Computer plays {} from the book",
+                board.position.to_san(&mv)
+            );
+            writer.write(PieceMoveEvent {
+                board: board_entity,
+                from: mv.from,
+                to: mv.to,
+                promotion: mv.promotion,
+            });
+            return;
+        }
+
+        let Some(thinking) = &mut computer.thinking else {
+            let engine = computer.engine.clone();
+            let limits = computer.limits.clone();
+            let position = board.position.clone();
+            let stop = Arc::new(AtomicBool::new(false));
+            let task_stop = stop.clone();
+
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let mut engine = engine.lock().unwrap();
+                engine.search(&position, &limits, &task_stop, |info| {
+                    debug!("info {}", info)
+                })
+            });
+
+            computer.thinking = Some(Thinking { key, stop, task });
+            return;
+        };
+
+        let Some(result) = block_on(poll_once(&mut thinking.task)) else {
+            return;
+        };
+        computer.thinking = None;
+
+        if let Some(mv) = result {
+            println!("Computer plays {}", board.position.to_san(&mv));
+            writer.write(PieceMoveEvent {
+                board: board_entity,
+                from: mv.from,
+                to: mv.to,
+                promotion: mv.promotion,
+            });
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Animating pieces between squares, and out of the game when captured.
+//!
+//! The board's model is updated as soon as a move is played; animations only
+//! move the pieces' transforms to catch up with it.
+
+use bevy::prelude::*;
+
+/// Seconds a move takes regardless of its length, at normal speed.
+const MOVE_SECONDS: f32 = 0.15;
+
+/// Seconds added to a move for each cell it crosses, at normal speed.
+const CELL_SECONDS: f32 = 0.06;
+
+/// Seconds a captured piece takes to disappear, at normal speed.
+const CAPTURE_SECONDS: f32 = 0.3;
+
+/// How high pieces jumping over others rise, relative to the size of a cell.
+const ARC_HEIGHT: f32 = 0.8;
+
+/// How animations are played, set with `--animation-speed`.
+#[derive(Resource)]
+pub struct AnimationSettings {
+    /// A multiple of the normal speed. Zero turns animations off.
+    pub speed: f32,
+
+    /// Whether pieces jump straight to their squares, toggled with "A" for
+    /// playing through games quickly.
+    pub skip: bool,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            skip: false,
+        }
+    }
+}
+
+impl AnimationSettings {
+    pub fn enabled(&self) -> bool {
+        !self.skip && self.speed > 0.0
+    }
+
+    /// How long a move across `cells` cells takes.
+    pub fn move_seconds(&self, cells: f32) -> f32 {
+        (MOVE_SECONDS + CELL_SECONDS * cells) / self.speed
+    }
+
+    pub fn capture_seconds(&self) -> f32 {
+        CAPTURE_SECONDS / self.speed
+    }
+
+    /// Toggle skipping animations with "A".
+    pub fn on_keyboard(
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        mut settings: ResMut<AnimationSettings>,
+    ) {
+        if !keyboard_input.just_pressed(KeyCode::KeyA) {
+            return;
+        }
+
+        settings.skip = !settings.skip;
+        if settings.skip {
+            println!("Animations off.");
+        } else {
+            println!("Animations on.");
+        }
+    }
+}
+
+/// A piece moving from one transform to another.
+#[derive(Component, Clone)]
+pub struct PieceTween {
+    from: Transform,
+    to: Transform,
+
+    /// How high the piece rises halfway, or zero to slide along the board.
+    height: f32,
+
+    /// Seconds to wait before starting, so moves can follow each other.
+    delay: f32,
+    duration: f32,
+    elapsed: f32,
+
+    /// Whether the piece is despawned once it arrives, for captured pieces.
+    despawn: bool,
+}
+
+impl PieceTween {
+    /// Slide along the board from `from` to `to`.
+    pub fn slide(from: Transform, to: Transform, duration: f32) -> Self {
+        Self {
+            from,
+            to,
+            height: 0.0,
+            delay: 0.0,
+            duration,
+            elapsed: 0.0,
+            despawn: false,
+        }
+    }
+
+    /// Jump from `from` to `to` over whatever is in between.
+    pub fn arc(from: Transform, to: Transform, duration: f32, cell_size: f32) -> Self {
+        Self {
+            height: cell_size * ARC_HEIGHT,
+            ..Self::slide(from, to, duration)
+        }
+    }
+
+    /// Shrink into the board, then despawn.
+    pub fn capture(from: Transform, duration: f32) -> Self {
+        let mut to = from;
+        to.scale = Vec3::ZERO;
+        Self {
+            despawn: true,
+            ..Self::slide(from, to, duration)
+        }
+    }
+
+    pub fn after(self, delay: f32) -> Self {
+        Self { delay, ..self }
+    }
+
+    /// Animate a piece that has been placed at `transform` from `from`,
+    /// unless animations are off. `tween` is made with the start and end of
+    /// the animation.
+    pub fn start(
+        commands: &mut Commands,
+        settings: &AnimationSettings,
+        entity: Entity,
+        transform: &mut Transform,
+        from: Transform,
+        tween: impl FnOnce(Transform, Transform) -> PieceTween,
+    ) {
+        if !settings.enabled() {
+            commands.entity(entity).remove::<PieceTween>();
+            return;
+        }
+
+        let to = *transform;
+        *transform = from;
+        commands.entity(entity).insert(tween(from, to));
+    }
+
+    pub fn on_update(
+        mut commands: Commands,
+        time: Res<Time>,
+        settings: Res<AnimationSettings>,
+        mut tweens_query: Query<(Entity, &mut PieceTween, &mut Transform)>,
+    ) {
+        for (entity, mut tween, mut transform) in &mut tweens_query {
+            tween.elapsed += time.delta_secs();
+
+            // Skipping finishes whatever is under way.
+            let t = if settings.enabled() {
+                ((tween.elapsed - tween.delay) / tween.duration.max(f32::EPSILON)).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+
+            if t >= 1.0 {
+                if tween.despawn {
+                    commands.entity(entity).despawn();
+                } else {
+                    *transform = tween.to;
+                    commands.entity(entity).remove::<PieceTween>();
+                }
+                continue;
+            }
+
+            let eased = EaseFunction::CubicInOut.sample_clamped(t);
+            transform.translation = tween.from.translation.lerp(tween.to.translation, eased)
+                + Vec3::Y * tween.height * 4.0 * t * (1.0 - t);
+            transform.rotation = tween.from.rotation.slerp(tween.to.rotation, eased);
+            transform.scale = tween.from.scale.lerp(tween.to.scale, eased);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 //! Chess board coordinates
 
-#[derive(Debug, strum_macros::EnumIter)]
+#[derive(Debug, Clone, Copy, strum_macros::EnumIter)]
 pub enum Direction {
     Up,
     UpRight,
@@ -183,6 +183,53 @@ impl BoardCoordinate {
         }
     }
 
+    /// The file (column) of the coordinate where `0` is the `A` file.
+    pub fn file(&self) -> usize {
+        self.as_coords().0
+    }
+
+    /// The rank (row) of the coordinate where `0` is the first rank.
+    pub fn rank(&self) -> usize {
+        self.as_coords().1
+    }
+
+    /// A rank-major index of the coordinate where `A1` is `0` and `H8` is `63`.
+    pub fn index(&self) -> usize {
+        let (x, y) = self.as_coords();
+        y * 8 + x
+    }
+
+    /// The inverse of [BoardCoordinate::index].
+    pub fn from_index(index: usize) -> Self {
+        Self::try_from((index % 8, index / 8))
+            .unwrap_or_else(|e| panic!("Invalid square index {}: {}", index, e))
+    }
+
+    /// Like [BoardCoordinate::try_transform] but without describing why the
+    /// transform failed. Useful in hot loops such as move generation.
+    pub fn offset(self, x: i32, y: i32) -> Option<Self> {
+        let (cur_x, cur_y) = self.as_coords();
+        let new_x = cur_x as i32 + x;
+        let new_y = cur_y as i32 + y;
+        if !(0..8).contains(&new_x) || !(0..8).contains(&new_y) {
+            return None;
+        }
+
+        Some(Self::from_index(new_y as usize * 8 + new_x as usize))
+    }
+
+    /// The coordinate as it's written in chess notation, e.g. `e4`.
+    pub fn algebraic(&self) -> String {
+        self.to_string().to_lowercase()
+    }
+
+    /// Parse a coordinate written in chess notation, e.g. `e4`.
+    pub fn from_algebraic(text: &str) -> Result<Self, String> {
+        text.to_uppercase()
+            .parse()
+            .map_err(|_| format!("Invalid square `{}`", text))
+    }
+
     pub fn try_transform(self, x: i32, y: i32) -> Result<Self, String> {
         let (cur_x, cur_y) = self.as_coords();
         let new_x = cur_x as i32 + x;
@@ -0,0 +1,365 @@
+//! Opening books in the Polyglot `.bin` format, and a panel listing the book
+//! moves for the position on the board.
+//!
+//! A book is a list of 16-byte entries sorted by position key, each giving a
+//! move and how often it should be played. Keys are [Position::hash_key].
+
+use std::path::Path;
+
+use bevy::asset::io::{AssetSource, AssetSourceBuilder, Reader};
+use bevy::asset::{AssetLoader, AssetPath, LoadContext};
+use bevy::prelude::*;
+
+use super::{ChessBoard, ChessPieceType, Coord, Move, Position};
+use crate::assets::AssetLibrary;
+
+/// The name of the game's book in the [AssetLibrary].
+pub const BOOK: &str = "BOOK";
+
+/// The asset source for the folder holding the book, which needn't be the
+/// asset folder.
+const BOOK_SOURCE: &str = "book";
+
+/// The size of an entry in a `.bin` file.
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct BookEntry {
+    key: u64,
+
+    /// The move packed as Polyglot does. See [BookEntry::to_move].
+    mv: u16,
+    weight: u16,
+}
+
+impl BookEntry {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            key: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            mv: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+        }
+    }
+
+    /// Unpack the move, which is stored as the destination file and rank in
+    /// the lowest bits, then the origin, then the promotion piece.
+    fn to_move(self, position: &Position) -> Option<Move> {
+        let field = |shift: u16| (self.mv >> shift & 0b111) as usize;
+        let from = Coord::from_index(field(9) * 8 + field(6));
+        let mut to = Coord::from_index(field(3) * 8 + field(0));
+        let promotion = match field(12) {
+            0 => None,
+            1 => Some(ChessPieceType::Knight),
+            2 => Some(ChessPieceType::Bishop),
+            3 => Some(ChessPieceType::Rook),
+            4 => Some(ChessPieceType::Queen),
+            _ => return None,
+        };
+
+        // Castling is written as the king taking its own rook.
+        let piece = position.piece_at(from)?;
+        if piece.kind == ChessPieceType::King
+            && position
+                .piece_at(to)
+                .is_some_and(|target| target.team == piece.team)
+        {
+            let file = if to.file() > from.file() { 6 } else { 2 };
+            to = Coord::from_index(from.rank() * 8 + file);
+        }
+
+        Some(Move {
+            from,
+            to,
+            promotion,
+        })
+    }
+}
+
+#[derive(Asset, TypePath, Debug)]
+pub struct OpeningBook {
+    entries: Vec<BookEntry>,
+}
+
+impl OpeningBook {
+    /// Read a book from the contents of a `.bin` file.
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(format!(
+                "A Polyglot book is a whole number of {} byte entries but got {} bytes",
+                ENTRY_SIZE,
+                bytes.len()
+            ));
+        }
+
+        let mut entries = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(BookEntry::parse)
+            .collect::<Vec<_>>();
+
+        // Books should already be sorted, but searching relies on it.
+        entries.sort_by_key(|entry| entry.key);
+        Ok(Self { entries })
+    }
+
+    /// Let the asset server read the book at `path`, wherever it is kept.
+    /// This must happen before the [AssetPlugin] is added.
+    pub fn register_source(app: &mut App, path: &str) {
+        let folder = match Path::new(path).parent() {
+            Some(folder) if !folder.as_os_str().is_empty() => folder,
+            _ => Path::new("."),
+        };
+        let folder = std::path::absolute(folder).unwrap_or_else(|_| folder.to_path_buf());
+        app.register_asset_source(
+            BOOK_SOURCE,
+            AssetSourceBuilder::default().with_reader(AssetSource::get_default_reader(
+                folder.to_string_lossy().to_string(),
+            )),
+        );
+    }
+
+    /// Where the asset server finds the book at `path`, once
+    /// [OpeningBook::register_source] has been called for it.
+    pub fn asset_path(path: &str) -> AssetPath<'static> {
+        let file = Path::new(path).file_name().unwrap_or_default();
+        AssetPath::from_path(Path::new(file))
+            .with_source(BOOK_SOURCE)
+            .into_owned()
+    }
+
+    /// The game's book, if one was loaded.
+    pub fn from_library<'a>(
+        asset_library: &AssetLibrary,
+        books: &'a Assets<OpeningBook>,
+    ) -> Option<&'a OpeningBook> {
+        books.get(asset_library.get_book(BOOK)?)
+    }
+
+    /// The legal book moves for `position` and their weights, most played first.
+    pub fn moves(&self, position: &Position) -> Vec<(Move, u16)> {
+        let key = position.hash_key();
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let legal_moves = position.legal_moves();
+
+        let mut moves = self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| Some((entry.to_move(position)?, entry.weight)))
+            .filter(|(mv, _)| legal_moves.contains(mv))
+            .collect::<Vec<_>>();
+        moves.sort_by_key(|(_, weight)| std::cmp::Reverse(*weight));
+        moves
+    }
+
+    /// Pick a book move with probability in proportion to its weight, using
+    /// `roll` as the source of randomness.
+    pub fn choose(&self, position: &Position, roll: u64) -> Option<Move> {
+        let moves = self.moves(position);
+        let total = moves.iter().map(|(_, weight)| *weight as u64).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        let mut remaining = roll % total;
+        for (mv, weight) in moves {
+            if remaining < weight as u64 {
+                return Some(mv);
+            }
+            remaining -= weight as u64;
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct OpeningBookLoader;
+
+impl AssetLoader for OpeningBookLoader {
+    type Asset = OpeningBook;
+    type Settings = ();
+    type Error = std::io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        OpeningBook::parse(&bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["bin"]
+    }
+}
+
+/// The text of the panel listing book moves.
+#[derive(Component)]
+pub struct BookExplorer;
+
+impl BookExplorer {
+    pub fn spawn(commands: &mut Commands) {
+        commands.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.),
+                right: Val::Px(10.),
+                padding: UiRect::all(Val::Px(10.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.1, 0.1, 0.1, 0.8)),
+            children![(
+                BookExplorer,
+                Text::default(),
+                TextFont {
+                    font_size: 18.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.9, 0.9, 0.9)),
+            )],
+        ));
+    }
+
+    /// List the book moves for the position on the board.
+    pub fn on_update(
+        mut text: Single<&mut Text, With<BookExplorer>>,
+        board: Single<&ChessBoard>,
+        asset_library: Res<AssetLibrary>,
+        books: Res<Assets<OpeningBook>>,
+    ) {
+        let Some(book) = OpeningBook::from_library(&asset_library, &books) else {
+            return;
+        };
+
+        let position = &board.position;
+        let moves = book.moves(position);
+        let total = moves.iter().map(|(_, weight)| *weight as u32).sum::<u32>();
+
+        let mut lines = vec!["Opening book".to_string()];
+        if moves.is_empty() {
+            lines.push("Out of book".to_string());
+        }
+        for (mv, weight) in moves {
+            lines.push(format!(
+                "{:<8}{:>6}{:>5.0}%",
+                position.to_san(&mv),
+                weight,
+                weight as f32 * 100. / total.max(1) as f32
+            ));
+        }
+
+        let lines = lines.join("\n");
+        if text.0 != lines {
+            text.0 = lines;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pack a book entry the way Polyglot writes them.
+    fn entry(position: &Position, mv: &str, weight: u16) -> Vec<u8> {
+        let coord = |text: &str| Coord::from_algebraic(text).unwrap();
+        let (from, to) = (coord(&mv[0..2]), coord(&mv[2..4]));
+        let packed = (from.rank() << 9 | from.file() << 6 | to.rank() << 3 | to.file()) as u16;
+
+        let mut bytes = position.hash_key().to_be_bytes().to_vec();
+        bytes.extend(packed.to_be_bytes());
+        bytes.extend(weight.to_be_bytes());
+        bytes.extend([0; 4]);
+        bytes
+    }
+
+    #[test]
+    fn weighted_moves_for_position() {
+        let start = Position::starting();
+        let bytes = [
+            entry(&start, "d2d4", 10),
+            entry(&start, "e2e4", 30),
+            // Moves that aren't legal are left out.
+            entry(&start, "e2e5", 50),
+        ]
+        .concat();
+        let book = OpeningBook::parse(&bytes).unwrap();
+
+        let moves = book
+            .moves(&start)
+            .into_iter()
+            .map(|(mv, weight)| (mv.to_string(), weight))
+            .collect::<Vec<_>>();
+        assert_eq!(moves, [("e2e4".to_string(), 30), ("d2d4".to_string(), 10)]);
+
+        assert_eq!(book.choose(&start, 0).unwrap().to_string(), "e2e4");
+        assert_eq!(book.choose(&start, 29).unwrap().to_string(), "e2e4");
+        assert_eq!(book.choose(&start, 30).unwrap().to_string(), "d2d4");
+        assert!(book.moves(&Position::empty()).is_empty());
+    }
+
+    #[test]
+    fn castling_is_king_takes_rook() {
+        let position = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let bytes = [entry(&position, "e1h1", 1), entry(&position, "e1a1", 1)].concat();
+        let book = OpeningBook::parse(&bytes).unwrap();
+
+        let moves = book
+            .moves(&position)
+            .into_iter()
+            .map(|(mv, _)| mv.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(moves, ["e1g1", "e1c1"]);
+    }
+
+    #[test]
+    fn truncated_book_is_rejected() {
+        assert!(OpeningBook::parse(&[0; 17]).is_err());
+    }
+
+    #[test]
+    fn book_is_loaded_from_its_own_source() {
+        let path = OpeningBook::asset_path("/home/player/books/performance.bin");
+        assert_eq!(path.to_string(), "book://performance.bin");
+        let path = OpeningBook::asset_path("performance.bin");
+        assert_eq!(path.to_string(), "book://performance.bin");
+    }
+
+    /// A book outside the asset folder loads through the asset server.
+    #[test]
+    fn book_loads_through_the_asset_server() {
+        let folder = std::env::temp_dir().join(format!("book-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("test.bin");
+        let start = Position::starting();
+        std::fs::write(&path, entry(&start, "e2e4", 1)).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let mut app = App::new();
+        OpeningBook::register_source(&mut app, &path);
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<OpeningBook>()
+            .init_asset_loader::<OpeningBookLoader>();
+        let handle: Handle<OpeningBook> = app
+            .world()
+            .resource::<AssetServer>()
+            .load(OpeningBook::asset_path(&path));
+
+        for _ in 0..1000 {
+            app.update();
+            let state = app
+                .world()
+                .resource::<AssetServer>()
+                .get_load_state(&handle);
+            if !state.is_some_and(|state| state.is_loading()) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        let books = app.world().resource::<Assets<OpeningBook>>();
+        assert_eq!(books.get(&handle).unwrap().moves(&start).len(), 1);
+    }
+}
@@ -0,0 +1,148 @@
+//! Moving pieces by dragging them, as an alternative to clicking the piece
+//! and then its destination.
+
+use bevy::prelude::*;
+
+use super::{
+    ActiveTeam, AnimationSettings, ChessBoard, ChessPiece, Coord, GameConfig, PieceMoveEvent,
+    PieceSelection, PieceTween,
+};
+
+/// How high a piece is lifted while dragged, relative to the size of a cell.
+const LIFT: f32 = 0.5;
+
+/// A piece being dragged and the square it was picked up from.
+#[derive(Component)]
+pub struct Dragging {
+    from: Coord,
+}
+
+impl Dragging {
+    /// Pick up a piece of the side to move.
+    pub fn observer_drag_start(
+        mut trigger: Trigger<Pointer<DragStart>>,
+        mut commands: Commands,
+        pieces_query: Query<(&ChessPiece, &ChildOf)>,
+        board_query: Query<&ChessBoard>,
+        active_team: Res<ActiveTeam>,
+        config: Res<GameConfig>,
+        mut selection: ResMut<PieceSelection>,
+    ) {
+        trigger.propagate(false);
+        if config.computer == Some(active_team.0) {
+            return;
+        }
+
+        let piece_entity = trigger.target();
+        let Ok((piece, piece_relationship)) = pieces_query.get(piece_entity) else {
+            return;
+        };
+        if piece.team != active_team.0 {
+            return;
+        }
+        let Ok(board) = board_query.get(piece_relationship.parent()) else {
+            return;
+        };
+
+        selection.piece = Some(piece_entity);
+        commands
+            .entity(piece_entity)
+            .remove::<PieceTween>()
+            .insert(Dragging {
+                from: board.occupants[&piece_entity],
+            });
+    }
+
+    /// Keep the piece under the pointer, lifted above the board.
+    pub fn observer_drag(
+        mut trigger: Trigger<Pointer<Drag>>,
+        mut pieces_query: Query<(&Dragging, &mut Transform, &ChildOf)>,
+        board_query: Query<(&ChessBoard, &Transform), Without<Dragging>>,
+        camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    ) {
+        trigger.propagate(false);
+        let Ok((dragging, mut transform, piece_relationship)) =
+            pieces_query.get_mut(trigger.target())
+        else {
+            return;
+        };
+        let Ok((board, board_transform)) = board_query.get(piece_relationship.parent()) else {
+            return;
+        };
+
+        // Follow the pointer across the plane of the board's surface.
+        let (camera, camera_transform) = *camera;
+        let surface = board.get_cell_translation(&dragging.from, board_transform);
+        let Some(point) = camera
+            .viewport_to_world(camera_transform, trigger.pointer_location.position)
+            .ok()
+            .and_then(|ray| {
+                ray.intersect_plane(surface, InfinitePlane3d::new(Vec3::Y))
+                    .map(|distance| ray.get_point(distance))
+            })
+        else {
+            return;
+        };
+
+        transform.translation = point + Vec3::Y * board.cell_size(board_transform) * LIFT;
+    }
+
+    /// Drop the piece on the nearest square, submitting the move. The piece
+    /// heads back to where it was picked up unless the move is played, so
+    /// illegal moves return it there.
+    pub fn observer_drag_end(
+        mut trigger: Trigger<Pointer<DragEnd>>,
+        mut commands: Commands,
+        mut pieces_query: Query<(&ChessPiece, &Dragging, &mut Transform, &ChildOf)>,
+        board_query: Query<(&ChessBoard, &Transform), Without<Dragging>>,
+        mut selection: ResMut<PieceSelection>,
+        mut writer: EventWriter<PieceMoveEvent>,
+        animation_settings: Res<AnimationSettings>,
+    ) {
+        trigger.propagate(false);
+        let piece_entity = trigger.target();
+        let Ok((piece, dragging, mut transform, piece_relationship)) =
+            pieces_query.get_mut(piece_entity)
+        else {
+            return;
+        };
+        let board_entity = piece_relationship.parent();
+        let Ok((board, board_transform)) = board_query.get(board_entity) else {
+            return;
+        };
+
+        let dropped = *transform;
+        let from = dragging.from;
+        *transform = board.get_cell_transform(&from, board_transform, &piece.team);
+        PieceTween::start(
+            &mut commands,
+            &animation_settings,
+            piece_entity,
+            &mut transform,
+            dropped,
+            |from, to| PieceTween::slide(from, to, animation_settings.move_seconds(0.0)),
+        );
+        commands.entity(piece_entity).remove::<Dragging>();
+
+        // Dropping a piece back where it came from leaves it selected, so a
+        // slight drag during a click doesn't lose the selection.
+        let Some(to) = board.nearest_cell(dropped.translation, board_transform) else {
+            println!("Dropped off the board.");
+            selection.piece = None;
+            return;
+        };
+        if to == from {
+            return;
+        }
+
+        let movement = PieceMoveEvent {
+            board: board_entity,
+            from,
+            to,
+            promotion: None,
+        };
+        println!("Submitting movement: {}", movement);
+        writer.write(movement);
+        selection.piece = None;
+    }
+}
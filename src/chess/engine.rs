@@ -0,0 +1,672 @@
+//! A computer player: iterative deepening alpha-beta search over [Position].
+//!
+//! Like the rules model, nothing here depends on the ECS so the search can run
+//! on a background thread.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::position::mix;
+use super::{Move, Position};
+
+mod eval;
+use eval::{evaluate, piece_value};
+
+/// Scores at or beyond this are forced mates.
+const MATE: i32 = 30_000;
+
+/// The deepest the search will go, including quiescence and check extensions.
+const MAX_PLY: usize = 128;
+
+/// Scores beyond this are mates within [MAX_PLY].
+const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+/// How many nodes are searched between checks of the clock and stop flag.
+const CHECK_INTERVAL: u64 = 1_024;
+
+/// The number of transposition table entries. Must be a power of two.
+const TABLE_SIZE: usize = 1 << 18;
+
+/// Bounds on how long a search may take. Searching stops at whichever limit
+/// is reached first.
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub depth: u32,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+
+    /// The most centipawns randomly added to or taken from each evaluation,
+    /// so weaker levels misjudge positions and make mistakes.
+    pub inaccuracy: i32,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            depth: MAX_PLY as u32 / 2,
+            nodes: None,
+            time: Some(Duration::from_secs(1)),
+            inaccuracy: 0,
+        }
+    }
+}
+
+/// How strongly the computer plays.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum_macros::EnumIter,
+    strum_macros::EnumString,
+    strum_macros::Display,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum Difficulty {
+    Beginner,
+    Casual,
+    #[default]
+    Intermediate,
+    Advanced,
+    Expert,
+}
+
+impl Difficulty {
+    pub fn limits(&self) -> SearchLimits {
+        let (depth, nodes, millis, inaccuracy) = match self {
+            Difficulty::Beginner => (1, Some(1_000), 100, 300),
+            Difficulty::Casual => (2, Some(10_000), 250, 150),
+            Difficulty::Intermediate => (4, Some(100_000), 500, 50),
+            Difficulty::Advanced => (6, Some(1_000_000), 1_000, 0),
+            Difficulty::Expert => (MAX_PLY as u32 / 2, None, 3_000, 0),
+        };
+
+        SearchLimits {
+            depth,
+            nodes,
+            time: Some(Duration::from_millis(millis)),
+            inaccuracy,
+        }
+    }
+}
+
+/// How good a position is for the side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+
+    /// Mate in this many moves. Negative when the side to move is being mated.
+    Mate(i32),
+}
+
+impl Score {
+    fn from_search(score: i32) -> Self {
+        if score > MATE_BOUND {
+            Score::Mate((MATE - score + 1) / 2)
+        } else if score < -MATE_BOUND {
+            Score::Mate(-(MATE + score) / 2)
+        } else {
+            Score::Centipawns(score)
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    /// Written the way UCI reports scores, e.g. `cp 35` or `mate -2`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Score::Centipawns(score) => write!(f, "cp {}", score),
+            Score::Mate(moves) => write!(f, "mate {}", moves),
+        }
+    }
+}
+
+/// Progress reported after each completed depth.
+#[derive(Debug, Clone)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub score: Score,
+    pub nodes: u64,
+    pub time: Duration,
+
+    /// The expected line of play, starting with the best move.
+    pub pv: Vec<Move>,
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "depth {} score {} nodes {} time {} pv",
+            self.depth,
+            self.score,
+            self.nodes,
+            self.time.as_millis()
+        )?;
+        for mv in &self.pv {
+            write!(f, " {}", mv)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a stored score is exact or only a bound on the real one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TableEntry {
+    key: u64,
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best_move: Option<Move>,
+}
+
+/// Results of earlier searches, indexed by position hash.
+struct TranspositionTable {
+    entries: Vec<Option<TableEntry>>,
+}
+
+impl TranspositionTable {
+    fn new() -> Self {
+        Self {
+            entries: vec![None; TABLE_SIZE],
+        }
+    }
+
+    fn get(&self, key: u64) -> Option<TableEntry> {
+        self.entries[key as usize & (TABLE_SIZE - 1)].filter(|entry| entry.key == key)
+    }
+
+    fn insert(&mut self, entry: TableEntry) {
+        let slot = &mut self.entries[entry.key as usize & (TABLE_SIZE - 1)];
+
+        // Keep deeper results for the same position.
+        if slot.is_some_and(|old| old.key == entry.key && old.depth > entry.depth) {
+            return;
+        }
+        *slot = Some(entry);
+    }
+}
+
+/// A chess engine. The transposition table is kept between searches, so
+/// reusing an engine over a game makes later searches faster.
+pub struct Engine {
+    table: TranspositionTable,
+    nodes: u64,
+    max_nodes: Option<u64>,
+    started: Instant,
+    deadline: Option<Instant>,
+    aborted: bool,
+
+    /// See [SearchLimits::inaccuracy].
+    inaccuracy: i32,
+
+    /// Varies the inaccuracy from one search to the next.
+    seed: u64,
+
+    /// The best root move found so far in the current iteration.
+    root_best: Option<Move>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            table: TranspositionTable::new(),
+            nodes: 0,
+            max_nodes: None,
+            started: Instant::now(),
+            deadline: None,
+            aborted: false,
+            inaccuracy: 0,
+            seed: 0,
+            root_best: None,
+        }
+    }
+}
+
+impl Engine {
+    /// Find the best move in `position`, or `None` if there are no legal moves.
+    ///
+    /// `on_info` is called after each completed depth. Setting `stop` ends the
+    /// search early with the best move found so far.
+    pub fn search(
+        &mut self,
+        position: &Position,
+        limits: &SearchLimits,
+        stop: &AtomicBool,
+        mut on_info: impl FnMut(&SearchInfo),
+    ) -> Option<Move> {
+        let mut position = position.clone();
+        let mut best_move = *position.legal_moves().first()?;
+
+        self.nodes = 0;
+        self.max_nodes = limits.nodes;
+        self.started = Instant::now();
+        self.deadline = limits.time.map(|time| self.started + time);
+        self.aborted = false;
+        self.inaccuracy = limits.inaccuracy;
+        self.seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+
+        for depth in 1..=limits.depth.max(1) {
+            self.root_best = None;
+            let score = self.negamax(&mut position, stop, depth, 0, -MATE, MATE);
+
+            // The previous best move is searched first, so anything better
+            // found before running out of time can still be trusted.
+            if let Some(mv) = self.root_best {
+                best_move = mv;
+            }
+            if self.aborted {
+                break;
+            }
+
+            let info = SearchInfo {
+                depth,
+                score: Score::from_search(score),
+                nodes: self.nodes,
+                time: self.started.elapsed(),
+                pv: self.principal_variation(&mut position, depth),
+            };
+            on_info(&info);
+
+            // There's no point looking deeper once a forced mate is found.
+            if score.abs() > MATE_BOUND {
+                break;
+            }
+        }
+
+        Some(best_move)
+    }
+
+    /// Whether the search must stop now.
+    fn should_abort(&mut self, stop: &AtomicBool) -> bool {
+        if !self.aborted && self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.aborted = stop.load(Ordering::Relaxed)
+                || self.max_nodes.is_some_and(|nodes| self.nodes >= nodes)
+                || self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline);
+        }
+        self.aborted
+    }
+
+    /// Evaluate the position, misjudging it if the search is meant to be
+    /// inaccurate. The error for a position is the same throughout a search
+    /// so that transposed positions agree.
+    fn evaluate(&self, position: &Position) -> i32 {
+        let score = evaluate(position);
+        if self.inaccuracy == 0 {
+            return score;
+        }
+
+        let range = 2 * self.inaccuracy as u64 + 1;
+        let error = (mix(position.hash_key() ^ self.seed) % range) as i32 - self.inaccuracy;
+        score + error
+    }
+
+    fn negamax(
+        &mut self,
+        position: &mut Position,
+        stop: &AtomicBool,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.nodes += 1;
+        if self.should_abort(stop) {
+            return 0;
+        }
+
+        if ply > 0
+            && (position.halfmove_clock() >= 100
+                || position.repetitions() > 1
+                || position.is_insufficient_material())
+        {
+            return 0;
+        }
+        if ply >= MAX_PLY {
+            return self.evaluate(position);
+        }
+
+        // Look one move further when in check so mates aren't missed.
+        let in_check = position.is_in_check(position.side_to_move());
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth == 0 {
+            return self.quiescence(position, stop, ply, alpha, beta);
+        }
+
+        let key = position.hash_key();
+        let entry = self.table.get(key);
+        if let Some(entry) = entry.filter(|entry| ply > 0 && entry.depth >= depth) {
+            let score = score_from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => {}
+            }
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+        order_moves(
+            position,
+            &mut moves,
+            entry.and_then(|entry| entry.best_move),
+        );
+
+        let original_alpha = alpha;
+        let mut best_score = -MATE;
+        let mut best_move = None;
+        for mv in moves {
+            position.make_move(mv);
+            let score = -self.negamax(position, stop, depth - 1, ply + 1, -beta, -alpha);
+            position.unmake_move();
+
+            if self.aborted {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+                if ply == 0 {
+                    self.root_best = Some(mv);
+                }
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.insert(TableEntry {
+            key,
+            depth,
+            score: score_to_table(best_score, ply),
+            bound,
+            best_move,
+        });
+
+        best_score
+    }
+
+    /// Search captures and promotions until the position is quiet, so the
+    /// evaluation isn't taken in the middle of an exchange.
+    fn quiescence(
+        &mut self,
+        position: &mut Position,
+        stop: &AtomicBool,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.nodes += 1;
+        if self.should_abort(stop) {
+            return 0;
+        }
+
+        let stand_pat = self.evaluate(position);
+        if stand_pat >= beta || ply >= MAX_PLY {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves = position
+            .legal_moves()
+            .into_iter()
+            .filter(|mv| position.is_capture(mv) || mv.promotion.is_some())
+            .collect::<Vec<_>>();
+        order_moves(position, &mut moves, None);
+
+        let mut best_score = stand_pat;
+        for mv in moves {
+            position.make_move(mv);
+            let score = -self.quiescence(position, stop, ply + 1, -beta, -alpha);
+            position.unmake_move();
+
+            if self.aborted {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+            }
+            if score >= beta {
+                break;
+            }
+            alpha = alpha.max(score);
+        }
+
+        best_score
+    }
+
+    /// Follow the best moves stored in the table from `position`.
+    fn principal_variation(&self, position: &mut Position, depth: u32) -> Vec<Move> {
+        let mut pv = Vec::new();
+        while pv.len() < depth as usize {
+            let Some(mv) = self
+                .table
+                .get(position.hash_key())
+                .and_then(|entry| entry.best_move)
+                .filter(|mv| position.legal_moves().contains(mv))
+            else {
+                break;
+            };
+            position.make_move(mv);
+            pv.push(mv);
+        }
+
+        for _ in &pv {
+            position.unmake_move();
+        }
+        pv
+    }
+}
+
+/// A search running on its own thread, for the protocols that must keep
+/// reading commands while the engine thinks.
+pub struct BackgroundSearch {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl BackgroundSearch {
+    /// Run `search` on a new thread. It is given the flag to pass on to
+    /// [Engine::search] so the search can be ended early.
+    pub fn spawn(search: impl FnOnce(&AtomicBool) + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || search(&thread_stop));
+        Self { stop, thread }
+    }
+
+    /// Ask the search to finish with the best move found so far, without
+    /// waiting for it.
+    pub fn interrupt(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// End the search and wait for its thread to finish.
+    pub fn stop(self) {
+        self.interrupt();
+        self.thread.join().expect("The search thread panicked.");
+    }
+}
+
+/// Sort moves so the most promising are searched first: the remembered best
+/// move, then captures of the most valuable piece by the least valuable one,
+/// then promotions.
+fn order_moves(position: &Position, moves: &mut [Move], best_move: Option<Move>) {
+    moves.sort_by_cached_key(|mv| {
+        if Some(*mv) == best_move {
+            return i32::MIN;
+        }
+
+        let mut score = 0;
+        if position.is_capture(mv) {
+            let victim = position
+                .piece_at(mv.to)
+                .map_or(piece_value(super::ChessPieceType::Pawn), |piece| {
+                    piece_value(piece.kind)
+                });
+            let attacker = position
+                .piece_at(mv.from)
+                .map_or(0, |piece| piece_value(piece.kind));
+            score += 10_000 + 10 * victim - attacker;
+        }
+        if let Some(kind) = mv.promotion {
+            score += piece_value(kind);
+        }
+
+        -score
+    });
+}
+
+/// Mate scores are stored relative to the position rather than the root so
+/// they stay correct when reached by a different path.
+fn score_to_table(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score + ply as i32
+    } else if score < -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score - ply as i32
+    } else if score < -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::Team;
+
+    /// A fixed depth with no clock or randomness, so results are repeatable.
+    fn limits(depth: u32) -> SearchLimits {
+        SearchLimits {
+            depth,
+            nodes: None,
+            time: None,
+            inaccuracy: 0,
+        }
+    }
+
+    /// The best move in SAN and the last reported info.
+    fn search(engine: &mut Engine, fen: &str, depth: u32) -> (String, SearchInfo) {
+        let position = Position::from_fen(fen).unwrap();
+        let mut last = None;
+        let mv = engine
+            .search(&position, &limits(depth), &AtomicBool::new(false), |info| {
+                last = Some(info.clone())
+            })
+            .unwrap();
+        (position.to_san(&mv), last.unwrap())
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let (mv, info) = search(
+            &mut Engine::default(),
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            4,
+        );
+        assert_eq!(mv, "Ra8#");
+        assert_eq!(info.score, Score::Mate(1));
+    }
+
+    #[test]
+    fn does_not_stalemate_when_winning() {
+        // Taking the knight leaves Black without a move. The bishop can
+        // never check the king, so there is no mate to prefer instead.
+        let fen = "8/8/8/8/4P3/7n/p1K5/k4B2 w - - 0 1";
+        let mut position = Position::from_fen(fen).unwrap();
+        position.make_move(position.parse_move("f1h3").unwrap());
+        assert!(position.legal_moves().is_empty() && !position.is_in_check(Team::Black));
+
+        let (mv, info) = search(&mut Engine::default(), fen, 4);
+        assert_ne!(mv, "Bxh3");
+        assert!(matches!(info.score, Score::Centipawns(score) if score > 0));
+    }
+
+    #[test]
+    fn stopped_search_returns_a_legal_move() {
+        let position = Position::starting();
+        let stop = AtomicBool::new(true);
+        let mv = Engine::default()
+            .search(&position, &limits(MAX_PLY as u32 / 2), &stop, |_| {})
+            .unwrap();
+        assert!(position.legal_moves().contains(&mv));
+
+        let mut limits = limits(MAX_PLY as u32 / 2);
+        limits.nodes = Some(1);
+        let mv = Engine::default()
+            .search(&position, &limits, &AtomicBool::new(false), |_| {})
+            .unwrap();
+        assert!(position.legal_moves().contains(&mv));
+    }
+
+    #[test]
+    fn no_move_without_legal_moves() {
+        let position = Position::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+        let mv = Engine::default().search(&position, &limits(2), &AtomicBool::new(false), |_| {});
+        assert_eq!(mv, None);
+    }
+
+    /// Searching a position again, even reached by another move order, finds
+    /// it in the table and agrees with the first search for less work.
+    #[test]
+    fn repeated_position_hits_the_table() {
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let mut engine = Engine::default();
+        let (first_move, first) = search(&mut engine, fen, 4);
+
+        let mut position = Position::starting();
+        for text in ["e2e4", "b8c6", "g1f3", "e7e5"] {
+            let mv = position.parse_move(text).unwrap();
+            position.make_move(mv);
+        }
+        let entry = engine.table.get(position.hash_key()).unwrap();
+        assert_eq!(
+            position.to_san(&entry.best_move.unwrap()),
+            first_move,
+            "the table keeps the root's best move"
+        );
+
+        let (second_move, second) = search(&mut engine, fen, 4);
+        assert_eq!(second_move, first_move);
+        assert_eq!(second.score, first.score);
+        assert!(second.nodes < first.nodes);
+    }
+}
@@ -0,0 +1,149 @@
+//! Static evaluation from material and piece-square tables.
+//!
+//! The tables are those of Tomasz Michniewski's "Simplified Evaluation
+//! Function", written from White's side with the eighth rank first.
+
+use crate::chess::{ChessPieceType, Position, Team};
+
+/// The value of each kind of piece in centipawns.
+pub fn piece_value(kind: ChessPieceType) -> i32 {
+    match kind {
+        ChessPieceType::Pawn => 100,
+        ChessPieceType::Knight => 320,
+        ChessPieceType::Bishop => 330,
+        ChessPieceType::Rook => 500,
+        ChessPieceType::Queen => 900,
+        ChessPieceType::King => 0,
+    }
+}
+
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_MIDDLE_GAME_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+#[rustfmt::skip]
+const KING_END_GAME_TABLE: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+/// Non-pawn material, not counting kings, below which the end game begins.
+const END_GAME_MATERIAL: i32 = 1_300;
+
+/// Score the position in centipawns from the side to move's point of view.
+pub fn evaluate(position: &Position) -> i32 {
+    let pieces = position.pieces().collect::<Vec<_>>();
+
+    // Kings should hide in the middle game and come out once material is low.
+    let material = |team: Team| {
+        pieces
+            .iter()
+            .filter(|(_, piece)| piece.team == team && piece.kind != ChessPieceType::Pawn)
+            .map(|(_, piece)| piece_value(piece.kind))
+            .sum::<i32>()
+    };
+    let is_end_game =
+        material(Team::White) <= END_GAME_MATERIAL && material(Team::Black) <= END_GAME_MATERIAL;
+
+    let mut score = 0;
+    for (coord, piece) in pieces {
+        let table = match piece.kind {
+            ChessPieceType::Pawn => &PAWN_TABLE,
+            ChessPieceType::Knight => &KNIGHT_TABLE,
+            ChessPieceType::Bishop => &BISHOP_TABLE,
+            ChessPieceType::Rook => &ROOK_TABLE,
+            ChessPieceType::Queen => &QUEEN_TABLE,
+            ChessPieceType::King if is_end_game => &KING_END_GAME_TABLE,
+            ChessPieceType::King => &KING_MIDDLE_GAME_TABLE,
+        };
+
+        // Black reads the tables upside down.
+        let row = match piece.team {
+            Team::White => 7 - coord.rank(),
+            Team::Black => coord.rank(),
+        };
+        let value = piece_value(piece.kind) + table[row * 8 + coord.file()];
+
+        if piece.team == position.side_to_move() {
+            score += value;
+        } else {
+            score -= value;
+        }
+    }
+
+    score
+}
@@ -0,0 +1,544 @@
+//! Driving an external UCI engine, either as the computer opponent or to
+//! analyse the position on the board.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+use super::engine::Difficulty;
+use super::{
+    ActiveTeam, ChessBoard, ComputerPlayer, HistoryView, Move, PendingPromotion, PieceMoveEvent,
+    Position, Team,
+};
+
+/// How long an engine may take to answer the handshake or `isready`.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something an engine said that the game cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineOutput {
+    /// An `info` line, without the leading `info`.
+    Info(String),
+
+    /// The move from a `bestmove` line, in long algebraic notation.
+    BestMove(String),
+}
+
+/// A reply the engine owes before its output can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Awaiting {
+    /// The end of the handshake.
+    UciOk,
+
+    /// The answer to `isready`, after which nothing from earlier commands
+    /// is left to read.
+    ReadyOk,
+}
+
+/// A UCI engine running as a child process. Nothing waits on the engine:
+/// replies are picked up by [UciClient::read] as they arrive.
+pub struct UciClient {
+    child: Child,
+    stdin: ChildStdin,
+
+    /// Lines from the engine's stdout, locked only so resources can share it.
+    lines: Mutex<Receiver<String>>,
+
+    /// The name the engine gave, or its command if it gave none.
+    name: String,
+
+    /// The reply being waited for, if any, and when it was asked for.
+    awaiting: Option<(Awaiting, Instant)>,
+}
+
+impl UciClient {
+    /// Start the engine and begin the UCI handshake, which completes once
+    /// [UciClient::is_ready].
+    pub fn start(mut command: Command) -> Result<Self, String> {
+        let program = command.get_program().to_string_lossy().to_string();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start engine `{}`: {}", program, e))?;
+
+        let stdin = child.stdin.take().expect("Engine stdin is piped.");
+        let stdout = child.stdout.take().expect("Engine stdout is piped.");
+
+        // Read on another thread so the game never blocks on the engine.
+        let (sender, lines) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            child,
+            stdin,
+            lines: Mutex::new(lines),
+            name: program,
+            awaiting: None,
+        };
+
+        client.send("uci")?;
+        client.awaiting = Some((Awaiting::UciOk, Instant::now()));
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the engine has caught up with every command sent so far.
+    pub fn is_ready(&self) -> bool {
+        self.awaiting.is_none()
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("Failed to write to engine `{}`: {}", self.name, e))
+    }
+
+    /// Start searching `position`, for `time` or until stopped if `None`.
+    pub fn go(&mut self, position: &Position, time: Option<Duration>) -> Result<(), String> {
+        self.send(&format!("position fen {}", position.to_fen()))?;
+        match time {
+            Some(time) => self.send(&format!("go movetime {}", time.as_millis())),
+            None => self.send("go infinite"),
+        }
+    }
+
+    /// Ask the engine to catch up with every command sent so far. Whatever
+    /// it says before then is dropped by [UciClient::read].
+    fn request_ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        self.awaiting = Some((Awaiting::ReadyOk, Instant::now()));
+        Ok(())
+    }
+
+    /// End the search, discarding its `bestmove` whether it was sent before
+    /// or after the engine was told to stop. A new search may be started
+    /// straight away.
+    pub fn stop(&mut self) -> Result<(), String> {
+        self.send("stop")?;
+        self.request_ready()
+    }
+
+    /// The next line of interest from the engine, waiting up to `timeout`
+    /// for each line. Fails if the engine has exited, or owes a reply for
+    /// longer than [RESPONSE_TIMEOUT].
+    pub fn read(&mut self, timeout: Duration) -> Result<Option<EngineOutput>, String> {
+        loop {
+            if let Some((_, since)) = self.awaiting {
+                if since.elapsed() > RESPONSE_TIMEOUT {
+                    return Err(format!("Engine `{}` did not answer in time", self.name));
+                }
+            }
+
+            let line = match self.lines.lock().unwrap().recv_timeout(timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(format!("Engine `{}` exited", self.name))
+                }
+            };
+
+            match self.awaiting {
+                Some((Awaiting::UciOk, _)) => {
+                    if let Some(name) = line.strip_prefix("id name ") {
+                        self.name = name.trim().to_string();
+                    } else if line.trim() == "uciok" {
+                        self.request_ready()?;
+                    }
+                }
+                Some((Awaiting::ReadyOk, _)) => {
+                    if line.trim() == "readyok" {
+                        self.awaiting = None;
+                    }
+                }
+                None => {
+                    let mut tokens = line.split_whitespace();
+                    match tokens.next() {
+                        Some("info") => {
+                            let info = tokens.collect::<Vec<_>>().join(" ");
+                            return Ok(Some(EngineOutput::Info(info)));
+                        }
+                        Some("bestmove") => {
+                            if let Some(mv) = tokens.next() {
+                                return Ok(Some(EngineOutput::BestMove(mv.to_string())));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for UciClient {
+    /// Ask the engine to quit, killing it if it doesn't.
+    fn drop(&mut self) {
+        if self.send("quit").is_ok() {
+            for _ in 0..10 {
+                if let Ok(Some(_)) = self.child.try_wait() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Describe an `info` line in SAN, e.g. `depth 12 score cp 35 pv Nf3 d5 g3`.
+/// Lines without a principal variation are not worth showing.
+fn describe_info(position: &Position, info: &str) -> Option<String> {
+    let (details, pv) = info.split_once(" pv ")?;
+    let mut details = details.split_whitespace();
+
+    let mut summary = Vec::new();
+    while let Some(token) = details.next() {
+        match token {
+            "depth" => summary.extend(["depth", details.next()?]),
+            "score" => summary.extend(["score", details.next()?, details.next()?]),
+            _ => {}
+        }
+    }
+
+    let mut position = position.clone();
+    let mut line = Vec::new();
+    for text in pv.split_whitespace() {
+        let Ok(mv) = position.parse_move(text) else {
+            break;
+        };
+        line.push(position.to_san(&mv));
+        position.make_move(mv);
+    }
+
+    Some(format!("{} pv {}", summary.join(" "), line.join(" ")))
+}
+
+/// An external engine and what it is used for.
+#[derive(Resource)]
+pub struct ExternalEngine {
+    client: UciClient,
+
+    /// The side the engine plays, or `None` to analyse instead.
+    plays: Option<Team>,
+    difficulty: Difficulty,
+
+    /// The position being searched, if any.
+    searching: Option<String>,
+
+    /// Whether the engine has finished its handshake and been announced.
+    introduced: bool,
+}
+
+impl ExternalEngine {
+    pub fn start(path: &str, plays: Option<Team>, difficulty: Difficulty) -> Result<Self, String> {
+        Ok(Self {
+            client: UciClient::start(Command::new(path))?,
+            plays,
+            difficulty,
+            searching: None,
+            introduced: false,
+        })
+    }
+
+    /// Translate an engine's best move into a move on the board.
+    fn move_event(
+        board_entity: Entity,
+        position: &Position,
+        text: &str,
+    ) -> Result<PieceMoveEvent, String> {
+        let mv = Move::from_long_algebraic(text)?;
+        if !position.legal_moves().contains(&mv) {
+            return Err(format!("`{}` is not a legal move", text));
+        }
+
+        Ok(PieceMoveEvent {
+            board: board_entity,
+            from: mv.from,
+            to: mv.to,
+            promotion: mv.promotion,
+        })
+    }
+
+    /// Start searching the position shown on the board.
+    fn search(&mut self, position: &Position, fen: String) {
+        let move_time = self.difficulty.limits().time;
+        let time = self
+            .plays
+            .map(|_| move_time.unwrap_or(Duration::from_secs(1)));
+        match self.client.go(position, time) {
+            Ok(()) => self.searching = Some(fen),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Give the engine positions to search and act on its answers, falling
+    /// back to the built-in engine if it stops answering.
+    pub fn on_update(
+        mut commands: Commands,
+        mut engine: ResMut<ExternalEngine>,
+        board: Single<(Entity, &ChessBoard)>,
+        active_team: Res<ActiveTeam>,
+        pending_promotion: Res<PendingPromotion>,
+        view: Option<Res<HistoryView>>,
+        mut writer: EventWriter<PieceMoveEvent>,
+    ) {
+        let (board_entity, board) = *board;
+        let fen = board.position.to_fen();
+        let is_current = |engine: &ExternalEngine| engine.searching.as_deref() == Some(&fen);
+
+        loop {
+            let output = match engine.client.read(Duration::ZERO) {
+                Ok(Some(output)) => output,
+                Ok(None) => break,
+                Err(e) => {
+                    println!("{}", e);
+                    commands.remove_resource::<ExternalEngine>();
+                    if let Some(team) = engine.plays {
+                        commands.insert_resource(ComputerPlayer::new(team, engine.difficulty));
+                    }
+                    return;
+                }
+            };
+            match output {
+                EngineOutput::Info(info) => {
+                    if engine.plays.is_none() && is_current(&engine) {
+                        if let Some(analysis) = describe_info(&board.position, &info) {
+                            println!("Analysis: {}", analysis);
+                        }
+                    }
+                }
+                EngineOutput::BestMove(text) => {
+                    let is_current = is_current(&engine);
+                    engine.searching = None;
+                    if engine.plays != Some(active_team.0) || !is_current || view.is_some() {
+                        continue;
+                    }
+
+                    match ExternalEngine::move_event(board_entity, &board.position, &text) {
+                        Ok(event) => {
+                            println!(
+                                "{} plays {}",
+                                engine.client.name(),
+                                board.position.to_san(&event.as_move())
+                            );
+                            writer.write(event);
+                        }
+                        Err(e) => println!("{}: {}", engine.client.name(), e),
+                    }
+                }
+            }
+        }
+
+        // The board moved on while the engine was thinking, so its answer
+        // would be for the wrong position.
+        if engine.searching.is_some() && !is_current(&engine) {
+            if let Err(e) = engine.client.stop() {
+                println!("{}", e);
+            }
+            engine.searching = None;
+        }
+
+        if !engine.client.is_ready() {
+            return;
+        }
+        if !engine.introduced {
+            engine.introduced = true;
+            match engine.plays {
+                Some(team) => println!("{} plays {}", engine.client.name(), team),
+                None => println!("{} is analysing", engine.client.name()),
+            }
+        }
+
+        if engine.searching.is_some() || board.position.legal_moves().is_empty() {
+            return;
+        }
+        // Earlier positions being viewed are analysed but not played from.
+        match engine.plays {
+            Some(team)
+                if team == active_team.0 && !pending_promotion.is_pending() && view.is_none() =>
+            {
+                engine.search(&board.position, fen)
+            }
+            Some(_) => {}
+            None => engine.search(&board.position, fen),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A shell script that speaks just enough UCI to always play `e2e4`.
+    const STAND_IN_ENGINE: &str = r#"
+while read -r line; do
+    case "$line" in
+        uci) echo "id name Stand-in"; echo "uciok" ;;
+        isready) echo "readyok" ;;
+        go*) echo "info depth 1 score cp 30 pv e2e4 e7e5"; echo "bestmove e2e4" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#;
+
+    /// Read from `client` until it has caught up, which fails the test if
+    /// it takes longer than [RESPONSE_TIMEOUT].
+    fn wait_until_ready(client: &mut UciClient) {
+        while !client.is_ready() {
+            assert_eq!(client.read(Duration::from_millis(10)).unwrap(), None);
+        }
+    }
+
+    fn stand_in_engine() -> UciClient {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(STAND_IN_ENGINE);
+        let mut client = UciClient::start(command).unwrap();
+        wait_until_ready(&mut client);
+        client
+    }
+
+    #[test]
+    fn handshake_reads_engine_name() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(STAND_IN_ENGINE);
+        let mut client = UciClient::start(command).unwrap();
+        assert!(!client.is_ready());
+
+        wait_until_ready(&mut client);
+        assert_eq!(client.name(), "Stand-in");
+    }
+
+    #[test]
+    fn best_move_becomes_move_event() {
+        let mut client = stand_in_engine();
+        let position = Position::starting();
+        client
+            .go(&position, Some(Duration::from_millis(10)))
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let info = client.read(timeout).unwrap().unwrap();
+        assert_eq!(
+            info,
+            EngineOutput::Info("depth 1 score cp 30 pv e2e4 e7e5".to_string())
+        );
+        let EngineOutput::Info(info) = info else {
+            unreachable!()
+        };
+        assert_eq!(
+            describe_info(&position, &info).unwrap(),
+            "depth 1 score cp 30 pv e4 e5"
+        );
+
+        let Some(EngineOutput::BestMove(text)) = client.read(timeout).unwrap() else {
+            panic!("Expected a best move");
+        };
+        let event = ExternalEngine::move_event(Entity::PLACEHOLDER, &position, &text).unwrap();
+        assert_eq!(event.to_string(), "e2e4");
+    }
+
+    /// However the `bestmove` of a stopped search races the `stop`, it is
+    /// never mistaken for the answer to the next search.
+    #[test]
+    fn stopped_search_leaves_nothing_behind() {
+        let mut client = stand_in_engine();
+        let position = Position::starting();
+        let timeout = Duration::from_secs(5);
+
+        // The search finishes before it is stopped, and its `bestmove` is
+        // still waiting to be read.
+        client
+            .go(&position, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(matches!(
+            client.read(timeout).unwrap(),
+            Some(EngineOutput::Info(_))
+        ));
+        client.stop().unwrap();
+        assert_eq!(client.read(Duration::from_millis(100)).unwrap(), None);
+        assert!(client.is_ready());
+
+        client
+            .go(&position, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(matches!(
+            client.read(timeout).unwrap(),
+            Some(EngineOutput::Info(_))
+        ));
+        assert_eq!(
+            client.read(timeout).unwrap(),
+            Some(EngineOutput::BestMove("e2e4".to_string()))
+        );
+    }
+
+    /// The next search can start before the engine confirms the stop, and
+    /// only its own output is read.
+    #[test]
+    fn search_starts_without_waiting_for_stop() {
+        let mut client = stand_in_engine();
+        let position = Position::starting();
+        let timeout = Duration::from_secs(5);
+
+        client
+            .go(&position, Some(Duration::from_millis(10)))
+            .unwrap();
+        client.stop().unwrap();
+        assert!(!client.is_ready());
+        client
+            .go(&position, Some(Duration::from_millis(10)))
+            .unwrap();
+
+        assert!(matches!(
+            client.read(timeout).unwrap(),
+            Some(EngineOutput::Info(_))
+        ));
+        assert!(client.is_ready());
+        assert_eq!(
+            client.read(timeout).unwrap(),
+            Some(EngineOutput::BestMove("e2e4".to_string()))
+        );
+        assert_eq!(client.read(Duration::from_millis(100)).unwrap(), None);
+    }
+
+    #[test]
+    fn exited_engine_is_an_error() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exit 0");
+        // Depending on how soon it exits, even sending `uci` may fail.
+        let result =
+            UciClient::start(command).and_then(|mut client| client.read(Duration::from_secs(5)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn illegal_best_move_is_rejected() {
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let result = ExternalEngine::move_event(Entity::PLACEHOLDER, &position, "e2e4");
+        assert_eq!(result.unwrap_err(), "`e2e4` is not a legal move");
+    }
+
+    #[test]
+    fn missing_engine_fails_to_start() {
+        let result = UciClient::start(Command::new("/nonexistent/engine"));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Recording the moves of a game.
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use super::pgn::PgnGame;
+use super::{GameResult, Move, Position};
+
+/// The `has_moved` flags of the pieces a move disturbed, as they were
+/// before the move, so that undoing it can restore them.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MovedFlags {
+    pub piece: bool,
+    pub captured: bool,
+}
+
+/// A move that was applied to the board.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub mv: Move,
+
+    /// The move in SAN, as written from the position it was played in.
+    pub san: String,
+
+    pub moved: MovedFlags,
+}
+
+/// Every move applied to the board since the game started, along with any
+/// moves that were undone and can be redone.
+#[derive(Resource)]
+pub struct GameHistory {
+    starting_position: Position,
+    entries: Vec<HistoryEntry>,
+    result: Option<GameResult>,
+
+    /// Undone moves, the most recently undone last.
+    undone: Vec<HistoryEntry>,
+}
+
+impl GameHistory {
+    pub fn new(starting_position: Position) -> Self {
+        Self {
+            starting_position,
+            entries: Vec::new(),
+            result: None,
+            undone: Vec::new(),
+        }
+    }
+
+    /// Record a move, returning it in SAN. `position` is the position the
+    /// move was played in.
+    ///
+    /// Playing the next move to be redone keeps the rest of the redo stack;
+    /// any other move clears it.
+    pub fn record(&mut self, position: &Position, mv: Move, moved: MovedFlags) -> &str {
+        if self.undone.last().map(|entry| entry.mv) == Some(mv) {
+            self.undone.pop();
+        } else {
+            self.undone.clear();
+        }
+
+        self.entries.push(HistoryEntry {
+            mv,
+            san: position.to_san(&mv),
+            moved,
+        });
+        &self.entries.last().unwrap().san
+    }
+
+    /// Take back the most recent move, keeping it to be redone.
+    pub fn undo(&mut self) -> Option<HistoryEntry> {
+        let entry = self.entries.pop()?;
+        self.undone.push(entry.clone());
+        self.result = None;
+        Some(entry)
+    }
+
+    pub fn starting_position(&self) -> &Position {
+        &self.starting_position
+    }
+
+    /// The moves played so far, oldest first.
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// The position after the first `ply` moves.
+    pub fn position_at(&self, ply: usize) -> Position {
+        let mut position = self.starting_position.clone();
+        for entry in &self.entries[..ply] {
+            position.make_move(entry.mv);
+        }
+        position
+    }
+
+    /// The move that redoing would play.
+    pub fn redo_move(&self) -> Option<Move> {
+        self.undone.last().map(|entry| entry.mv)
+    }
+
+    pub fn set_result(&mut self, result: GameResult) {
+        self.result = Some(result);
+    }
+
+    pub fn to_pgn(&self) -> PgnGame {
+        let moves = self.entries.iter().map(|entry| entry.san.clone()).collect();
+        PgnGame::new(&self.starting_position, moves, self.result)
+    }
+
+    /// Write the game to a PGN file, returning where it was written.
+    pub fn save_pgn(&self) -> std::io::Result<PathBuf> {
+        // Under `bazel run` the working directory is the runfiles tree, so
+        // prefer the workspace the command was run from.
+        let directory = std::env::var_os("BUILD_WORKSPACE_DIRECTORY")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let path = directory.join("game.pgn");
+
+        std::fs::write(&path, self.to_pgn().to_pgn())?;
+        Ok(path)
+    }
+
+    /// Save the game when "P" is pressed.
+    pub fn on_save_pgn(keyboard_input: Res<ButtonInput<KeyCode>>, history: Res<GameHistory>) {
+        if !keyboard_input.just_pressed(KeyCode::KeyP) {
+            return;
+        }
+
+        match history.save_pgn() {
+            Ok(path) => println!("Saved game to {}", path.display()),
+            Err(e) => println!("Failed to save game: {}", e),
+        }
+    }
+}
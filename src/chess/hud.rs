@@ -0,0 +1,437 @@
+//! The overlay shown during a game: whose turn it is, the moves played, the
+//! pieces each side has captured and banners for check and the end of the
+//! game.
+//!
+//! Clicking a move shows the board as it was after that move. Moves can't be
+//! played until the board is brought back to the game, by clicking the last
+//! move or pressing "Escape".
+
+use bevy::input::mouse::{AccumulatedMouseScroll, MouseScrollUnit};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::ui::RelativeCursorPosition;
+
+use super::{
+    ActiveTeam, AssetLibrary, ChessBoard, ChessPiece, ChessPieceType, GameHistory,
+    PendingPromotion, Piece, PieceSelection, Position, Team,
+};
+
+const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const PANEL_COLOR: Color = Color::srgba(0.1, 0.1, 0.1, 0.8);
+const MOVE_COLOR: Color = Color::NONE;
+const SHOWN_MOVE_COLOR: Color = Color::srgb(0.25, 0.45, 0.25);
+
+/// How far a line of mouse wheel scrolling moves the list, in pixels.
+const SCROLL_LINE_HEIGHT: f32 = 24.;
+
+/// The board is showing an earlier position of the game, and moves can't be
+/// played until it's brought back.
+#[derive(Resource)]
+pub struct HistoryView {
+    /// How many moves of the game are shown.
+    ply: usize,
+
+    /// The position of the game being played, to go back to.
+    live: Position,
+}
+
+/// Whose turn it is.
+#[derive(Component)]
+pub struct TurnText;
+
+/// The pieces taken by a side, and how much material it is ahead by.
+#[derive(Component)]
+pub struct CapturedText(Team);
+
+/// Announces check and the end of the game.
+#[derive(Component)]
+pub struct BannerText;
+
+/// The scrolling list of moves.
+#[derive(Component)]
+pub struct MoveList;
+
+/// A move in the list, showing the position after it when clicked.
+#[derive(Component)]
+pub struct MoveButton {
+    /// The number of moves played once this one has been.
+    ply: usize,
+}
+
+/// The value of a piece in pawns, as players count material.
+fn points(kind: ChessPieceType) -> i32 {
+    match kind {
+        ChessPieceType::Pawn => 1,
+        ChessPieceType::Knight | ChessPieceType::Bishop => 3,
+        ChessPieceType::Rook => 5,
+        ChessPieceType::Queen => 9,
+        ChessPieceType::King => 0,
+    }
+}
+
+fn hud_text(font_size: f32) -> impl Bundle {
+    (
+        Text::default(),
+        TextFont {
+            font_size,
+            ..default()
+        },
+        TextColor(TEXT_COLOR),
+    )
+}
+
+pub struct Hud;
+
+impl Hud {
+    pub fn spawn(commands: &mut Commands) {
+        commands.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.),
+                left: Val::Px(10.),
+                width: Val::Px(240.),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.),
+                padding: UiRect::all(Val::Px(10.)),
+                ..default()
+            },
+            BackgroundColor(PANEL_COLOR),
+            children![
+                (TurnText, hud_text(22.)),
+                (CapturedText(Team::White), hud_text(16.)),
+                (CapturedText(Team::Black), hud_text(16.)),
+                (
+                    MoveList,
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        max_height: Val::Vh(60.),
+                        overflow: Overflow::scroll_y(),
+                        ..default()
+                    },
+                    ScrollPosition::default(),
+                    RelativeCursorPosition::default(),
+                ),
+            ],
+        ));
+
+        commands.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.),
+                width: Val::Percent(100.),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            Pickable::IGNORE,
+            children![(
+                BannerText,
+                hud_text(36.),
+                Node {
+                    padding: UiRect::axes(Val::Px(20.), Val::Px(8.)),
+                    display: Display::None,
+                    ..default()
+                },
+                BackgroundColor(Color::srgba(0.5, 0.1, 0.1, 0.85)),
+            )],
+        ));
+    }
+
+    /// Keep the turn, captured pieces and banner in step with the board,
+    /// whenever a move is played, taken back or a different one is shown.
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    pub fn on_update(
+        board: Single<Ref<ChessBoard>>,
+        history: Res<GameHistory>,
+        view: Option<Res<HistoryView>>,
+        active_team: Res<ActiveTeam>,
+        mut turn_text: Single<&mut Text, (With<TurnText>, Without<BannerText>)>,
+        mut captured_query: Query<
+            (&CapturedText, &mut Text),
+            (Without<TurnText>, Without<BannerText>),
+        >,
+        banner: Single<(&mut Text, &mut Node), (With<BannerText>, Without<TurnText>)>,
+        mut shown: Local<Option<usize>>,
+    ) {
+        let ply = view
+            .as_ref()
+            .map_or(history.entries().len(), |view| view.ply);
+        if !history.is_changed()
+            && !board.is_changed()
+            && !active_team.is_changed()
+            && *shown == Some(ply)
+        {
+            return;
+        }
+        *shown = Some(ply);
+
+        let position = &board.position;
+
+        let mut turn = format!("{} to move", active_team.0);
+        if ply < history.entries().len() {
+            turn = format!("{}\nViewing an earlier move, Esc to return", turn);
+        }
+        set_text(&mut turn_text, turn);
+
+        // Count up what each side took in the moves shown.
+        let mut captured = HashMap::<Team, Vec<Piece>>::new();
+        let mut replayed = history.starting_position().clone();
+        for entry in &history.entries()[..ply] {
+            let square = replayed
+                .en_passant_capture_square(&entry.mv)
+                .unwrap_or(entry.mv.to);
+            if let Some(piece) = replayed.piece_at(square) {
+                captured
+                    .entry(piece.team.opponent())
+                    .or_default()
+                    .push(piece);
+            }
+            replayed.make_move(entry.mv);
+        }
+
+        let material = |team: Team| -> i32 {
+            captured.get(&team).map_or(0, |pieces| {
+                pieces.iter().map(|piece| points(piece.kind)).sum()
+            })
+        };
+        for (captured_text, mut text) in &mut captured_query {
+            let team = captured_text.0;
+            let mut pieces = captured.get(&team).cloned().unwrap_or_default();
+            pieces.sort_by_key(|piece| std::cmp::Reverse(points(piece.kind)));
+
+            let mut line = format!("{} took:", team);
+            for piece in pieces {
+                line.push(' ');
+                line.push(piece.to_fen_char().to_ascii_uppercase());
+            }
+            let lead = material(team) - material(team.opponent());
+            if lead > 0 {
+                line.push_str(&format!("  +{}", lead));
+            }
+            set_text(&mut text, line);
+        }
+
+        let team = position.side_to_move();
+        let announcement = match position.outcome() {
+            Some((result, reason)) => format!("{} ({})", capitalize(&reason.to_string()), result),
+            None if position.is_in_check(team) => format!("{} is in check", team),
+            None => String::new(),
+        };
+        let (mut banner_text, mut banner_node) = banner.into_inner();
+        let display = if announcement.is_empty() {
+            Display::None
+        } else {
+            Display::Flex
+        };
+        if banner_node.display != display {
+            banner_node.display = display;
+        }
+        set_text(&mut banner_text, announcement);
+    }
+
+    /// Rebuild the move list when moves are played or taken back, or a
+    /// different move is shown.
+    pub fn on_update_moves(
+        mut commands: Commands,
+        history: Res<GameHistory>,
+        view: Option<Res<HistoryView>>,
+        list: Single<(Entity, &mut ScrollPosition), With<MoveList>>,
+        mut shown: Local<Option<usize>>,
+    ) {
+        let ply = view
+            .as_ref()
+            .map_or(history.entries().len(), |view| view.ply);
+        if !history.is_changed() && *shown == Some(ply) {
+            return;
+        }
+        *shown = Some(ply);
+
+        let (list_entity, mut scroll) = list.into_inner();
+        commands.entity(list_entity).despawn_related::<Children>();
+
+        let entries = history.entries();
+        let start = history.starting_position();
+        // Games set up with Black to move start the list with "1...".
+        let offset = match start.side_to_move() {
+            Team::White => 0,
+            Team::Black => 1,
+        };
+        let first_move = start.fullmove_number();
+
+        let rows = (entries.len() + offset).div_ceil(2);
+        for row in 0..rows {
+            let row_entity = commands
+                .spawn((
+                    Node {
+                        column_gap: Val::Px(6.),
+                        ..default()
+                    },
+                    children![(
+                        Text::new(format!("{}.", first_move as usize + row)),
+                        TextFont {
+                            font_size: 16.,
+                            ..default()
+                        },
+                        TextColor(TEXT_COLOR),
+                        Node {
+                            width: Val::Px(36.),
+                            ..default()
+                        },
+                    )],
+                ))
+                .id();
+            commands.entity(list_entity).add_child(row_entity);
+
+            for column in 0..2 {
+                let index = (row * 2 + column).checked_sub(offset);
+                let label = match index.and_then(|index| entries.get(index)) {
+                    Some(entry) => entry.san.clone(),
+                    None if index.is_none() => "...".to_string(),
+                    None => continue,
+                };
+                let mut cell = commands.spawn((
+                    Node {
+                        width: Val::Px(80.),
+                        padding: UiRect::horizontal(Val::Px(4.)),
+                        ..default()
+                    },
+                    BackgroundColor(MOVE_COLOR),
+                    children![(
+                        Text::new(label),
+                        TextFont {
+                            font_size: 16.,
+                            ..default()
+                        },
+                        TextColor(TEXT_COLOR),
+                    )],
+                ));
+                if let Some(index) = index {
+                    cell.insert((Button, MoveButton { ply: index + 1 }));
+                    if index + 1 == ply {
+                        cell.insert(BackgroundColor(SHOWN_MOVE_COLOR));
+                    }
+                }
+                let cell = cell.id();
+                commands.entity(row_entity).add_child(cell);
+            }
+        }
+
+        // Follow the game as it is played. Layout clamps this to the end.
+        if view.is_none() {
+            scroll.offset_y = f32::MAX;
+        }
+    }
+
+    /// Scroll the move list with the mouse wheel while over it.
+    pub fn on_scroll(
+        mouse_scroll: Res<AccumulatedMouseScroll>,
+        list: Single<(&mut ScrollPosition, &RelativeCursorPosition), With<MoveList>>,
+    ) {
+        let (mut scroll, cursor) = list.into_inner();
+        if mouse_scroll.delta.y == 0. || !cursor.mouse_over() {
+            return;
+        }
+
+        let delta = match mouse_scroll.unit {
+            MouseScrollUnit::Line => mouse_scroll.delta.y * SCROLL_LINE_HEIGHT,
+            MouseScrollUnit::Pixel => mouse_scroll.delta.y,
+        };
+        scroll.offset_y = (scroll.offset_y - delta).max(0.);
+    }
+}
+
+impl HistoryView {
+    /// Show the position after a clicked move, or go back to the game with
+    /// "Escape" or by clicking its last move. While a promotion piece is being
+    /// chosen "Escape" belongs to [PendingPromotion], which cancels the move,
+    /// so this runs before it and leaves the key alone.
+    #[allow(clippy::too_many_arguments)]
+    pub fn on_update(
+        mut commands: Commands,
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        buttons_query: Query<(&Interaction, &MoveButton), Changed<Interaction>>,
+        view: Option<Res<HistoryView>>,
+        board: Single<(Entity, &mut ChessBoard, &Transform), Without<ChessPiece>>,
+        pieces_query: Query<&ChessPiece>,
+        asset_library: Res<AssetLibrary>,
+        gltf_assets: Res<Assets<Gltf>>,
+        history: Res<GameHistory>,
+        pending_promotion: Res<PendingPromotion>,
+        mut active_team: ResMut<ActiveTeam>,
+        mut selection: ResMut<PieceSelection>,
+    ) {
+        let clicked = buttons_query
+            .iter()
+            .find(|(interaction, _)| **interaction == Interaction::Pressed)
+            .map(|(_, button)| button.ply);
+        let ply = match clicked {
+            Some(ply) => ply,
+            None if keyboard_input.just_pressed(KeyCode::Escape)
+                && !pending_promotion.is_pending() =>
+            {
+                history.entries().len()
+            }
+            None => return,
+        };
+
+        let shown = view
+            .as_ref()
+            .map_or(history.entries().len(), |view| view.ply);
+        if ply == shown {
+            return;
+        }
+        if pending_promotion.is_pending() {
+            println!("A promotion piece must be chosen first.");
+            return;
+        }
+
+        let (board_entity, mut board, board_transform) = board.into_inner();
+        let live = match view {
+            Some(view) => view.live.clone(),
+            None => board.position.clone(),
+        };
+        let is_live = ply == history.entries().len();
+        let position = if is_live {
+            live.clone()
+        } else {
+            history.position_at(ply)
+        };
+
+        board.show_position(
+            board_entity,
+            &position,
+            &mut commands,
+            &pieces_query,
+            &asset_library,
+            &gltf_assets,
+            board_transform,
+        );
+        active_team.0 = position.side_to_move();
+        selection.piece = None;
+
+        if is_live {
+            commands.remove_resource::<HistoryView>();
+            println!("Back to the game.");
+        } else {
+            commands.insert_resource(HistoryView { ply, live });
+            println!(
+                "Viewing the position after move {}: {}",
+                ply,
+                position.to_fen()
+            );
+        }
+    }
+}
+
+fn set_text(text: &mut Text, value: String) {
+    if text.0 != value {
+        text.0 = value;
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,141 @@
+//! Showing where the selected piece can move.
+
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::prelude::*;
+
+use super::{ChessBoard, Move, PieceSelection, Position};
+
+/// How far markers float above the board, relative to the size of a cell, so
+/// they aren't hidden by its surface.
+const MARKER_LIFT: f32 = 0.02;
+
+/// The kinds of move a marker can show, each drawn differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MarkerKind {
+    Quiet,
+    Capture,
+    Castling,
+    Promotion,
+}
+
+impl MarkerKind {
+    fn of(position: &Position, mv: &Move) -> Self {
+        if mv.promotion.is_some() {
+            MarkerKind::Promotion
+        } else if position.castling_side(mv).is_some() {
+            MarkerKind::Castling
+        } else if position.is_capture(mv) {
+            MarkerKind::Capture
+        } else {
+            MarkerKind::Quiet
+        }
+    }
+}
+
+/// A highlight on a square the selected piece can move to.
+#[derive(Component)]
+pub struct MoveMarker;
+
+/// The meshes and materials markers are drawn with, sized for a cell of 1.
+#[derive(Resource)]
+pub struct MarkerAssets {
+    quiet: (Handle<Mesh>, Handle<StandardMaterial>),
+    capture: (Handle<Mesh>, Handle<StandardMaterial>),
+    castling: (Handle<Mesh>, Handle<StandardMaterial>),
+    promotion: (Handle<Mesh>, Handle<StandardMaterial>),
+}
+
+impl FromWorld for MarkerAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut material = |color: Color| {
+            world
+                .resource_mut::<Assets<StandardMaterial>>()
+                .add(StandardMaterial {
+                    base_color: color,
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                })
+        };
+        let quiet = material(Color::srgba(0.2, 0.8, 0.3, 0.7));
+        let capture = material(Color::srgba(0.9, 0.2, 0.2, 0.8));
+        let castling = material(Color::srgba(0.2, 0.5, 0.9, 0.7));
+        let promotion = material(Color::srgba(0.95, 0.75, 0.1, 0.8));
+
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        Self {
+            quiet: (meshes.add(Circle::new(0.15)), quiet),
+            capture: (meshes.add(Annulus::new(0.38, 0.47)), capture),
+            castling: (meshes.add(Rectangle::new(0.6, 0.6)), castling),
+            promotion: (meshes.add(Rhombus::new(0.7, 0.7)), promotion),
+        }
+    }
+}
+
+impl MarkerAssets {
+    fn get(&self, kind: MarkerKind) -> &(Handle<Mesh>, Handle<StandardMaterial>) {
+        match kind {
+            MarkerKind::Quiet => &self.quiet,
+            MarkerKind::Capture => &self.capture,
+            MarkerKind::Castling => &self.castling,
+            MarkerKind::Promotion => &self.promotion,
+        }
+    }
+}
+
+impl MoveMarker {
+    /// Replace the markers whenever a different piece is selected.
+    pub fn on_update(
+        mut commands: Commands,
+        selection: Res<PieceSelection>,
+        markers_query: Query<Entity, With<MoveMarker>>,
+        board: Single<(Entity, &ChessBoard, &Transform)>,
+        marker_assets: Res<MarkerAssets>,
+    ) {
+        if !selection.is_changed() {
+            return;
+        }
+
+        for marker in &markers_query {
+            commands.entity(marker).despawn();
+        }
+
+        let (board_entity, board, board_transform) = *board;
+        let Some(from) = selection
+            .piece
+            .and_then(|piece| board.occupants.get(&piece).copied())
+        else {
+            return;
+        };
+        let Some(piece) = board.position.piece_at(from) else {
+            return;
+        };
+
+        let cell_size = board.cell_size(board_transform);
+
+        let mut moves = board.position.legal_moves_from(from);
+        // Each promotion piece is a separate move to the same square.
+        moves.dedup_by_key(|mv| mv.to);
+
+        for mv in moves {
+            let (mesh, material) = marker_assets.get(MarkerKind::of(&board.position, &mv));
+            let mut transform = board.get_cell_transform(&mv.to, board_transform, &piece.team);
+            transform.translation.y += cell_size * MARKER_LIFT;
+            transform.rotation *= Quat::from_rotation_x(-FRAC_PI_2);
+            transform.scale = Vec3::splat(cell_size);
+
+            let marker = commands
+                .spawn((
+                    MoveMarker,
+                    Mesh3d(mesh.clone()),
+                    MeshMaterial3d(material.clone()),
+                    transform,
+                    // Clicks go through to the board and pieces beneath.
+                    Pickable::IGNORE,
+                ))
+                .id();
+            commands.entity(board_entity).add_child(marker);
+        }
+    }
+}
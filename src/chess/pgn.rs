@@ -0,0 +1,487 @@
+//! Portable Game Notation (PGN) for recording and replaying games.
+
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{GameResult, Move, Position, Team};
+
+/// The longest line PGN export should produce.
+const MAX_LINE_LENGTH: usize = 80;
+
+/// A single game in PGN.
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    /// Tag pairs in the order they are written.
+    pub tags: Vec<(String, String)>,
+
+    /// The position the game started from.
+    pub starting_position: Position,
+
+    /// Each move in SAN.
+    pub moves: Vec<String>,
+
+    /// The result of the game, or `None` if it is still in progress.
+    pub result: Option<GameResult>,
+}
+
+impl PgnGame {
+    /// Describe a game played from `starting_position`, filling in the Seven Tag Roster.
+    pub fn new(
+        starting_position: &Position,
+        moves: Vec<String>,
+        result: Option<GameResult>,
+    ) -> Self {
+        let mut tags = vec![
+            ("Event".to_string(), "Casual Game".to_string()),
+            ("Site".to_string(), "?".to_string()),
+            ("Date".to_string(), today()),
+            ("Round".to_string(), "-".to_string()),
+            ("White".to_string(), "?".to_string()),
+            ("Black".to_string(), "?".to_string()),
+            ("Result".to_string(), result_text(result)),
+        ];
+
+        // Games that don't start from the standard position must say where they did start.
+        let fen = starting_position.to_fen();
+        if fen != Position::starting().to_fen() {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), fen));
+        }
+
+        Self {
+            tags,
+            starting_position: starting_position.clone(),
+            moves,
+            result,
+        }
+    }
+
+    /// Read every game in a PGN file. Comments, NAGs and variations are skipped.
+    pub fn parse_all(text: &str) -> Result<Vec<Self>, String> {
+        let mut games = Vec::new();
+        let mut tags = Vec::new();
+        let mut moves = Vec::new();
+
+        for token in tokenize(text)? {
+            match token {
+                Token::Tag(name, value) => {
+                    // A tag after movetext means the previous game ended without a result.
+                    if !moves.is_empty() {
+                        games.push(Self::from_parts(
+                            std::mem::take(&mut tags),
+                            std::mem::take(&mut moves),
+                            None,
+                        )?);
+                    }
+                    tags.push((name, value));
+                }
+                Token::Move(san) => moves.push(san),
+                Token::Result(result) => games.push(Self::from_parts(
+                    std::mem::take(&mut tags),
+                    std::mem::take(&mut moves),
+                    result,
+                )?),
+            }
+        }
+
+        if !tags.is_empty() || !moves.is_empty() {
+            games.push(Self::from_parts(tags, moves, None)?);
+        }
+
+        Ok(games)
+    }
+
+    fn from_parts(
+        tags: Vec<(String, String)>,
+        moves: Vec<String>,
+        result: Option<GameResult>,
+    ) -> Result<Self, String> {
+        let starting_position = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => {
+                Position::from_fen(fen).map_err(|e| format!("Invalid FEN tag: {}", e))?
+            }
+            None => Position::starting(),
+        };
+
+        Ok(Self {
+            tags,
+            starting_position,
+            moves,
+            result,
+        })
+    }
+
+    /// The value of a tag pair, if the game has it.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Play through the game, resolving each SAN move against the rules.
+    pub fn resolve_moves(&self) -> Result<Vec<Move>, String> {
+        let mut position = self.starting_position.clone();
+        let mut moves = Vec::with_capacity(self.moves.len());
+        for san in &self.moves {
+            let number = match position.side_to_move() {
+                Team::White => format!("{}.", position.fullmove_number()),
+                Team::Black => format!("{}...", position.fullmove_number()),
+            };
+            let mv = position
+                .parse_move(san)
+                .map_err(|e| format!("Illegal move {} {}: {}", number, san, e))?;
+            position.make_move(mv);
+            moves.push(mv);
+        }
+
+        Ok(moves)
+    }
+
+    /// Write the game as PGN text.
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        pgn.push('\n');
+
+        // Collect movetext tokens before wrapping them into lines.
+        let mut tokens = Vec::new();
+        let mut number = self.starting_position.fullmove_number();
+        let mut white_to_move = self.starting_position.side_to_move() == Team::White;
+        for (index, san) in self.moves.iter().enumerate() {
+            if white_to_move {
+                tokens.push(format!("{}.", number));
+            } else if index == 0 {
+                tokens.push(format!("{}...", number));
+            }
+            tokens.push(san.clone());
+
+            if !white_to_move {
+                number += 1;
+            }
+            white_to_move = !white_to_move;
+        }
+        tokens.push(result_text(self.result));
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+
+        pgn
+    }
+}
+
+/// A meaningful piece of PGN text.
+enum Token {
+    Tag(String, String),
+    Move(String),
+    Result(Option<GameResult>),
+}
+
+/// Split PGN text into tag pairs, moves and results, dropping everything else.
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line_start = true;
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            // Lines starting with `%` are escaped from parsing.
+            '%' if line_start => skip_line(&mut chars),
+            ';' => skip_line(&mut chars),
+            '{' => skip_comment(&mut chars)?,
+            '(' => skip_variation(&mut chars)?,
+            '[' => tokens.push(read_tag(&mut chars)?),
+            '$' => while chars.next_if(char::is_ascii_digit).is_some() {},
+            c => {
+                let mut symbol = String::from(c);
+                while let Some(c) =
+                    chars.next_if(|c| !c.is_whitespace() && !"{}()[];$".contains(*c))
+                {
+                    symbol.push(c);
+                }
+                tokens.extend(read_symbol(&symbol)?);
+            }
+        }
+        line_start = false;
+    }
+
+    Ok(tokens)
+}
+
+/// Interpret a run of movetext such as `12.`, `Nf3!?`, `3...e5` or `1-0`.
+fn read_symbol(symbol: &str) -> Result<Option<Token>, String> {
+    let result = match symbol {
+        "1-0" => Some(Some(GameResult::WhiteWins)),
+        "0-1" => Some(Some(GameResult::BlackWins)),
+        "1/2-1/2" => Some(Some(GameResult::Draw)),
+        "*" => Some(None),
+        _ => None,
+    };
+    if let Some(result) = result {
+        return Ok(Some(Token::Result(result)));
+    }
+
+    // Drop any move number in front of the move.
+    let san = match symbol.find('.') {
+        Some(_) if symbol.starts_with(|c: char| c.is_ascii_digit()) => {
+            symbol.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+        }
+        _ => symbol,
+    };
+
+    // Drop annotations like `!` and `?!`.
+    let san = san.trim_end_matches(['!', '?']);
+    if san.is_empty() {
+        return Ok(None);
+    }
+    if !san.starts_with(|c: char| c.is_ascii_alphabetic() || c == '0') {
+        return Err(format!("Unexpected `{}` in movetext", symbol));
+    }
+
+    Ok(Some(Token::Move(san.to_string())))
+}
+
+fn skip_line(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| *c != '\n').is_some() {}
+}
+
+fn skip_comment(chars: &mut Peekable<Chars>) -> Result<(), String> {
+    chars
+        .find(|c| *c == '}')
+        .map(|_| ())
+        .ok_or_else(|| "Unterminated `{` comment".to_string())
+}
+
+/// Skip a variation, including any nested variations and comments inside it.
+fn skip_variation(chars: &mut Peekable<Chars>) -> Result<(), String> {
+    let mut depth = 1;
+    while depth > 0 {
+        match chars.next() {
+            Some('(') => depth += 1,
+            Some(')') => depth -= 1,
+            Some('{') => skip_comment(chars)?,
+            Some(';') => skip_line(chars),
+            Some(_) => {}
+            None => return Err("Unterminated `(` variation".to_string()),
+        }
+    }
+    Ok(())
+}
+
+/// Read a tag pair like `[White "Kasparov, Garry"]` after its opening bracket.
+fn read_tag(chars: &mut Peekable<Chars>) -> Result<Token, String> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+    if chars.next() != Some('"') {
+        return Err(format!("Expected a quoted value for tag `{}`", name));
+    }
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => value.extend(chars.next()),
+            Some(c) => value.push(c),
+            None => return Err(format!("Unterminated value for tag `{}`", name)),
+        }
+    }
+
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.next() != Some(']') {
+        return Err(format!("Expected `]` to close tag `{}`", name));
+    }
+
+    Ok(Token::Tag(name, value))
+}
+
+/// The PGN result token for a game.
+fn result_text(result: Option<GameResult>) -> String {
+    match result {
+        Some(result) => result.to_string(),
+        None => "*".to_string(),
+    }
+}
+
+/// Today's date in the `YYYY.MM.DD` form of the PGN `Date` tag.
+fn today() -> String {
+    let Ok(elapsed) = SystemTime::now().duration_since(UNIX_EPOCH) else {
+        return "????.??.??".to_string();
+    };
+
+    // Convert days since the epoch to a civil date.
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (elapsed.as_secs() / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_GAMES: &str = r#"[Event "First"]
+[White "Someone \"Quoted\""]
+
+1. e4 {Best by test} e5 $1 2. Nf3 (2. f4 exf4 (2... d5) 3. Nf3) Nc6!? ; the main line
+3. Bb5 1-0
+
+[Event "Second"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+
+1. e4 Kd7 *
+"#;
+
+    #[test]
+    fn multiple_games() {
+        let games = PgnGame::parse_all(TWO_GAMES).unwrap();
+        assert_eq!(games.len(), 2);
+
+        let first = &games[0];
+        assert_eq!(first.tag("Event"), Some("First"));
+        assert_eq!(first.tag("White"), Some("Someone \"Quoted\""));
+        assert_eq!(first.moves, ["e4", "e5", "Nf3", "Nc6", "Bb5"]);
+        assert_eq!(first.result, Some(GameResult::WhiteWins));
+        assert_eq!(first.resolve_moves().unwrap().len(), 5);
+
+        let second = &games[1];
+        assert_eq!(second.tag("Event"), Some("Second"));
+        assert_eq!(
+            second.starting_position.to_fen(),
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"
+        );
+        assert_eq!(second.moves, ["e4", "Kd7"]);
+        assert_eq!(second.result, None);
+    }
+
+    #[test]
+    fn comments_nags_and_variations_are_skipped() {
+        let text = "% escaped line 1. d4\n1. e4 $14 {a {comment} e5 (1... c5 (1... e6 2. d4) 2. Nf3 {in a variation}) 2. Nf3 ; d4\n2... Nc6 *";
+        let games = PgnGame::parse_all(text).unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3", "Nc6"]);
+    }
+
+    #[test]
+    fn move_numbers_are_attached_or_separate() {
+        let games = PgnGame::parse_all("1.e4 e5 2.Nf3 2...Nc6 3. Bb5 a6 *").unwrap();
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6"]);
+    }
+
+    #[test]
+    fn illegal_move_names_its_number() {
+        let games = PgnGame::parse_all("1. e4 e5 2. Zz9 *").unwrap();
+        let error = games[0].resolve_moves().unwrap_err();
+        assert!(error.starts_with("Illegal move 2. Zz9"), "{}", error);
+
+        let games = PgnGame::parse_all("1. e4 e5 2. Nf3 Ke6 *").unwrap();
+        let error = games[0].resolve_moves().unwrap_err();
+        assert!(error.starts_with("Illegal move 2... Ke6"), "{}", error);
+    }
+
+    #[test]
+    fn export_has_seven_tag_roster() {
+        let game = PgnGame::new(
+            &Position::starting(),
+            vec!["e4".to_string(), "e5".to_string()],
+            Some(GameResult::Draw),
+        );
+        let names = game
+            .tags
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Event", "Site", "Date", "Round", "White", "Black", "Result"]
+        );
+        assert_eq!(game.tag("Result"), Some("1/2-1/2"));
+
+        let pgn = game.to_pgn();
+        assert!(pgn.starts_with("[Event \"Casual Game\"]\n"));
+        assert!(pgn.ends_with("\n\n1. e4 e5 1/2-1/2\n"));
+    }
+
+    #[test]
+    fn export_from_set_up_position() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 7";
+        let start = Position::from_fen(fen).unwrap();
+        let game = PgnGame::new(&start, vec!["Kd7".to_string(), "e4".to_string()], None);
+        assert_eq!(game.tag("SetUp"), Some("1"));
+        assert_eq!(game.tag("FEN"), Some(fen));
+
+        let pgn = game.to_pgn();
+        assert!(pgn.ends_with("\n7... Kd7 8. e4 *\n"), "{}", pgn);
+
+        // Reading the export back gives the same game.
+        let read = &PgnGame::parse_all(&pgn).unwrap()[0];
+        assert_eq!(read.starting_position.to_fen(), fen);
+        assert_eq!(read.moves, game.moves);
+    }
+
+    #[test]
+    fn export_wraps_long_movetext() {
+        let moves = ["Nf3", "Nf6", "Ng1", "Ng8"]
+            .iter()
+            .cycle()
+            .take(60)
+            .map(|san| san.to_string())
+            .collect::<Vec<_>>();
+        let game = PgnGame::new(&Position::starting(), moves.clone(), None);
+        let pgn = game.to_pgn();
+
+        let movetext = pgn.split("\n\n").nth(1).unwrap();
+        assert!(movetext.lines().count() > 1);
+        for line in movetext.lines() {
+            assert!(line.len() <= MAX_LINE_LENGTH, "`{}` is too long", line);
+            assert!(!line.starts_with(' ') && !line.ends_with(' '));
+        }
+        assert_eq!(PgnGame::parse_all(&pgn).unwrap()[0].moves, moves);
+    }
+
+    #[test]
+    fn unterminated_text_is_an_error() {
+        assert!(PgnGame::parse_all("1. e4 {never closed").is_err());
+        assert!(PgnGame::parse_all("1. e4 (1. d4 *").is_err());
+        assert!(PgnGame::parse_all("[Event \"Open").is_err());
+        assert!(PgnGame::parse_all("1. e4 ) *").is_err());
+    }
+}
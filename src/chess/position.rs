@@ -0,0 +1,1049 @@
+//! A headless model of a chess game.
+//!
+//! Nothing in this module depends on the ECS so rules can be evaluated without
+//! a running `App`. The [ChessBoard](super::ChessBoard) component owns a
+//! [Position] and keeps its entities in sync with it.
+
+use std::collections::HashSet;
+
+use strum::IntoEnumIterator;
+
+use super::board_coords::BoardCoordinate as Coord;
+use super::board_coords::Direction as BoardDir;
+use super::{ChessPieceType, Team};
+
+mod bitboard;
+mod fen;
+mod notation;
+mod perft;
+mod zobrist;
+
+pub(super) use bitboard::mix;
+use bitboard::{bit, squares, team_index, Bitboard};
+
+impl Team {
+    /// The team playing against this one.
+    pub fn opponent(&self) -> Team {
+        match self {
+            Team::Black => Team::White,
+            Team::White => Team::Black,
+        }
+    }
+
+    /// The direction pawns of this team advance in.
+    pub fn forward(&self) -> i32 {
+        match self {
+            Team::Black => -1,
+            Team::White => 1,
+        }
+    }
+
+    /// The rank pieces of this team start on.
+    pub fn back_rank(&self) -> usize {
+        match self {
+            Team::Black => 7,
+            Team::White => 0,
+        }
+    }
+}
+
+/// A piece as seen by the rules engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Piece {
+    pub kind: ChessPieceType,
+    pub team: Team,
+}
+
+impl Piece {
+    pub fn new(team: Team, kind: ChessPieceType) -> Self {
+        Self { kind, team }
+    }
+}
+
+/// The castling moves each team is still entitled to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CastlingRights {
+    pub white_king_side: bool,
+    pub white_queen_side: bool,
+    pub black_king_side: bool,
+    pub black_queen_side: bool,
+}
+
+impl CastlingRights {
+    pub fn all() -> Self {
+        Self {
+            white_king_side: true,
+            white_queen_side: true,
+            black_king_side: true,
+            black_queen_side: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Whether `team` may still castle towards `side`.
+    pub fn has(&self, team: Team, side: CastlingSide) -> bool {
+        match (team, side) {
+            (Team::White, CastlingSide::KingSide) => self.white_king_side,
+            (Team::White, CastlingSide::QueenSide) => self.white_queen_side,
+            (Team::Black, CastlingSide::KingSide) => self.black_king_side,
+            (Team::Black, CastlingSide::QueenSide) => self.black_queen_side,
+        }
+    }
+
+    /// Permanently remove the right for `team` to castle towards `side`.
+    pub fn revoke(&mut self, team: Team, side: CastlingSide) {
+        let right = match (team, side) {
+            (Team::White, CastlingSide::KingSide) => &mut self.white_king_side,
+            (Team::White, CastlingSide::QueenSide) => &mut self.white_queen_side,
+            (Team::Black, CastlingSide::KingSide) => &mut self.black_king_side,
+            (Team::Black, CastlingSide::QueenSide) => &mut self.black_queen_side,
+        };
+        *right = false;
+    }
+}
+
+/// The side of the board a king castles towards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum_macros::EnumIter)]
+pub enum CastlingSide {
+    KingSide,
+    QueenSide,
+}
+
+/// Where the king and rook start and finish when castling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CastlingSquares {
+    pub king_from: Coord,
+    pub king_to: Coord,
+    pub rook_from: Coord,
+    pub rook_to: Coord,
+}
+
+impl CastlingSide {
+    pub fn squares(&self, team: Team) -> CastlingSquares {
+        let rank = team.back_rank();
+        let square = |file: usize| Coord::from_index(rank * 8 + file);
+        match self {
+            CastlingSide::KingSide => CastlingSquares {
+                king_from: square(4),
+                king_to: square(6),
+                rook_from: square(7),
+                rook_to: square(5),
+            },
+            CastlingSide::QueenSide => CastlingSquares {
+                king_from: square(4),
+                king_to: square(2),
+                rook_from: square(0),
+                rook_to: square(3),
+            },
+        }
+    }
+}
+
+/// A single move of a piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: Coord,
+    pub to: Coord,
+
+    /// The piece a pawn becomes when reaching the far rank.
+    pub promotion: Option<ChessPieceType>,
+}
+
+impl Move {
+    pub fn new(from: Coord, to: Coord) -> Self {
+        Self {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+}
+
+/// How a finished game was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    pub fn win_for(team: Team) -> Self {
+        match team {
+            Team::Black => GameResult::BlackWins,
+            Team::White => GameResult::WhiteWins,
+        }
+    }
+}
+
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameResult::WhiteWins => write!(f, "1-0"),
+            GameResult::BlackWins => write!(f, "0-1"),
+            GameResult::Draw => write!(f, "1/2-1/2"),
+        }
+    }
+}
+
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOverReason {
+    Checkmate,
+    Stalemate,
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    InsufficientMaterial,
+}
+
+impl std::fmt::Display for GameOverReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameOverReason::Checkmate => write!(f, "checkmate"),
+            GameOverReason::Stalemate => write!(f, "stalemate"),
+            GameOverReason::FiftyMoveRule => write!(f, "fifty-move rule"),
+            GameOverReason::ThreefoldRepetition => write!(f, "threefold repetition"),
+            GameOverReason::InsufficientMaterial => write!(f, "insufficient material"),
+        }
+    }
+}
+
+/// The state needed to revert a move made by [Position::make_move].
+#[derive(Debug, Clone)]
+struct Undo {
+    /// The [Position::hash_key] before the move was made.
+    key: u64,
+    mv: Move,
+    moved: Piece,
+    captured: Option<Piece>,
+    castling: CastlingRights,
+    en_passant: Option<Coord>,
+    halfmove_clock: u32,
+}
+
+/// The complete state of a chess game at a point in time.
+#[derive(Debug, Clone)]
+pub struct Position {
+    /// Pieces indexed by [Coord::index].
+    squares: [Option<Piece>; 64],
+
+    /// The same pieces as sets of squares, by team and by kind, for move
+    /// generation and attack detection.
+    by_team: [Bitboard; 2],
+    by_kind: [Bitboard; 6],
+
+    side_to_move: Team,
+    castling: CastlingRights,
+
+    /// The square a pawn skipped over with a double step on the previous move.
+    en_passant: Option<Coord>,
+
+    /// Half moves since the last capture or pawn move.
+    halfmove_clock: u32,
+
+    /// Starts at 1 and is incremented after Black moves.
+    fullmove_number: u32,
+
+    /// The Zobrist key of the pieces, side to move and castling rights,
+    /// updated as they change. See [Position::hash_key].
+    key: u64,
+
+    /// Moves made since the position was set up, most recent last.
+    history: Vec<Undo>,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::starting()
+    }
+}
+
+impl Position {
+    /// A board with no pieces and White to move.
+    pub fn empty() -> Self {
+        Self {
+            squares: [None; 64],
+            by_team: [0; 2],
+            by_kind: [0; 6],
+            side_to_move: Team::White,
+            castling: CastlingRights::none(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            key: zobrist::white_to_move(),
+            history: Vec::new(),
+        }
+    }
+
+    /// The standard starting position.
+    pub fn starting() -> Self {
+        let mut position = Self::empty();
+
+        let back_rank = [
+            ChessPieceType::Rook,
+            ChessPieceType::Knight,
+            ChessPieceType::Bishop,
+            ChessPieceType::Queen,
+            ChessPieceType::King,
+            ChessPieceType::Bishop,
+            ChessPieceType::Knight,
+            ChessPieceType::Rook,
+        ];
+
+        for team in Team::iter() {
+            let rank = team.back_rank();
+            let pawn_rank = (rank as i32 + team.forward()) as usize;
+            for (file, kind) in back_rank.iter().enumerate() {
+                position.set_piece(
+                    Coord::from_index(rank * 8 + file),
+                    Some(Piece::new(team, *kind)),
+                );
+                position.set_piece(
+                    Coord::from_index(pawn_rank * 8 + file),
+                    Some(Piece::new(team, ChessPieceType::Pawn)),
+                );
+            }
+        }
+
+        position.set_castling(CastlingRights::all());
+        position
+    }
+
+    pub fn piece_at(&self, coord: Coord) -> Option<Piece> {
+        self.squares[coord.index()]
+    }
+
+    /// Place (or clear) a piece without making a move.
+    pub fn set_piece(&mut self, coord: Coord, piece: Option<Piece>) {
+        if let Some(old) = self.squares[coord.index()] {
+            self.key ^= zobrist::piece(coord, old);
+            self.by_team[team_index(old.team)] &= !bit(coord);
+            self.by_kind[old.kind as usize] &= !bit(coord);
+        }
+        if let Some(new) = piece {
+            self.key ^= zobrist::piece(coord, new);
+            self.by_team[team_index(new.team)] |= bit(coord);
+            self.by_kind[new.kind as usize] |= bit(coord);
+        }
+        self.squares[coord.index()] = piece;
+    }
+
+    fn set_side_to_move(&mut self, team: Team) {
+        if team != self.side_to_move {
+            self.key ^= zobrist::white_to_move();
+            self.side_to_move = team;
+        }
+    }
+
+    fn set_castling(&mut self, castling: CastlingRights) {
+        self.key ^= zobrist::castling(self.castling) ^ zobrist::castling(castling);
+        self.castling = castling;
+    }
+
+    /// The squares holding pieces of `team` of the given kind.
+    fn pieces_of(&self, team: Team, kind: ChessPieceType) -> Bitboard {
+        self.by_team[team_index(team)] & self.by_kind[kind as usize]
+    }
+
+    fn occupied(&self) -> Bitboard {
+        self.by_team[0] | self.by_team[1]
+    }
+
+    /// All pieces on the board and where they are.
+    pub fn pieces(&self) -> impl Iterator<Item = (Coord, Piece)> + '_ {
+        self.squares
+            .iter()
+            .enumerate()
+            .filter_map(|(index, piece)| piece.map(|piece| (Coord::from_index(index), piece)))
+    }
+
+    pub fn side_to_move(&self) -> Team {
+        self.side_to_move
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    /// Locate the king of the given team, if it has one.
+    pub fn king_square(&self, team: Team) -> Option<Coord> {
+        squares(self.pieces_of(team, ChessPieceType::King)).next()
+    }
+
+    /// Whether the king of the given team is currently attacked.
+    pub fn is_in_check(&self, team: Team) -> bool {
+        match self.king_square(team) {
+            Some(king) => self.is_attacked(king, team.opponent()),
+            None => false,
+        }
+    }
+
+    /// All squares the pieces of `team` attack, regardless of whose turn it is.
+    pub fn attacked_squares(&self, team: Team) -> HashSet<Coord> {
+        let occupied = self.occupied();
+        let mut attacked = 0;
+
+        for (from, piece) in self.pieces().filter(|(_, piece)| piece.team == team) {
+            attacked |= match piece.kind {
+                ChessPieceType::Pawn => bitboard::pawn_attacks(team, from),
+                ChessPieceType::Knight => bitboard::knight_attacks(from),
+                ChessPieceType::King => bitboard::king_attacks(from),
+                ChessPieceType::Rook => bitboard::rook_attacks(from, occupied),
+                ChessPieceType::Bishop => bitboard::bishop_attacks(from, occupied),
+                ChessPieceType::Queen => {
+                    bitboard::rook_attacks(from, occupied)
+                        | bitboard::bishop_attacks(from, occupied)
+                }
+            };
+        }
+
+        squares(attacked).collect()
+    }
+
+    /// Whether any piece of team `by` attacks `target`.
+    pub fn is_attacked(&self, target: Coord, by: Team) -> bool {
+        self.is_attacked_with(target, by, self.occupied(), 0)
+    }
+
+    /// Like [Position::is_attacked] but with the board occupied by
+    /// `occupied` and pieces on `captured` gone, to try out moves without
+    /// making them.
+    fn is_attacked_with(
+        &self,
+        target: Coord,
+        by: Team,
+        occupied: Bitboard,
+        captured: Bitboard,
+    ) -> bool {
+        let pieces = |kind| self.pieces_of(by, kind) & !captured;
+        let queens = pieces(ChessPieceType::Queen);
+
+        // Pawns attack diagonally forward, so a pawn of `by` attacks the
+        // target from where a pawn of the other team on the target would.
+        bitboard::pawn_attacks(by.opponent(), target) & pieces(ChessPieceType::Pawn) != 0
+            || bitboard::knight_attacks(target) & pieces(ChessPieceType::Knight) != 0
+            || bitboard::king_attacks(target) & pieces(ChessPieceType::King) != 0
+            || bitboard::rook_attacks(target, occupied) & (pieces(ChessPieceType::Rook) | queens)
+                != 0
+            || bitboard::bishop_attacks(target, occupied)
+                & (pieces(ChessPieceType::Bishop) | queens)
+                != 0
+    }
+
+    /// Generate all legal moves for the side to move.
+    ///
+    /// Moves which would leave the mover's own king attacked, whether by
+    /// walking into an attack, breaking a pin or ignoring a check, are excluded.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let team = self.side_to_move;
+        let Some(king) = self.king_square(team) else {
+            return self.pseudo_legal_moves();
+        };
+
+        self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|mv| {
+                // Castling already avoids attacked squares, and the rook
+                // can't expose the king, so only the piece moving and what
+                // it takes matter.
+                let captured = match self.en_passant_capture_square(mv) {
+                    Some(square) => bit(square),
+                    None => bit(mv.to),
+                };
+                let occupied = (self.occupied() & !bit(mv.from) & !captured) | bit(mv.to);
+                let king = if mv.from == king { mv.to } else { king };
+                !self.is_attacked_with(king, team.opponent(), occupied, captured)
+            })
+            .collect()
+    }
+
+    /// How the game has ended, if it has.
+    pub fn outcome(&self) -> Option<(GameResult, GameOverReason)> {
+        let team = self.side_to_move;
+        if self.legal_moves().is_empty() {
+            return Some(if self.is_in_check(team) {
+                (
+                    GameResult::win_for(team.opponent()),
+                    GameOverReason::Checkmate,
+                )
+            } else {
+                (GameResult::Draw, GameOverReason::Stalemate)
+            });
+        }
+
+        if self.halfmove_clock >= 100 {
+            return Some((GameResult::Draw, GameOverReason::FiftyMoveRule));
+        }
+
+        if self.repetitions() >= 3 {
+            return Some((GameResult::Draw, GameOverReason::ThreefoldRepetition));
+        }
+
+        if self.is_insufficient_material() {
+            return Some((GameResult::Draw, GameOverReason::InsufficientMaterial));
+        }
+
+        None
+    }
+
+    /// The Zobrist key of the position, equal for repeated positions.
+    ///
+    /// Keys are compatible with Polyglot opening books, so the en passant
+    /// file only counts when a pawn is able to capture there.
+    pub fn hash_key(&self) -> u64 {
+        let team = self.side_to_move;
+        let en_passant = self.en_passant.filter(|target| {
+            bitboard::pawn_attacks(team.opponent(), *target)
+                & self.pieces_of(team, ChessPieceType::Pawn)
+                != 0
+        });
+
+        match en_passant {
+            Some(target) => self.key ^ zobrist::en_passant(target.file()),
+            None => self.key,
+        }
+    }
+
+    /// How many times the current position has occurred, including now.
+    pub fn repetitions(&self) -> usize {
+        let key = self.hash_key();
+
+        // Positions before the last capture or pawn move can never recur.
+        let reversible = self.history.len().min(self.halfmove_clock as usize);
+        1 + self.history[self.history.len() - reversible..]
+            .iter()
+            .filter(|undo| undo.key == key)
+            .count()
+    }
+
+    /// Whether neither team has enough material left to deliver checkmate.
+    pub fn is_insufficient_material(&self) -> bool {
+        let mut bishop_colors = Vec::new();
+        let mut knights = 0;
+
+        for (coord, piece) in self.pieces() {
+            match piece.kind {
+                ChessPieceType::King => {}
+                ChessPieceType::Knight => knights += 1,
+                ChessPieceType::Bishop => bishop_colors.push((coord.file() + coord.rank()) % 2),
+                ChessPieceType::Pawn | ChessPieceType::Rook | ChessPieceType::Queen => {
+                    return false
+                }
+            }
+        }
+
+        match (knights, bishop_colors.len()) {
+            // A lone knight or bishop can't force mate.
+            (0, 0) | (1, 0) | (0, 1) => true,
+            // Neither can any number of bishops all on the same colour.
+            (0, _) => bishop_colors.iter().all(|color| *color == bishop_colors[0]),
+            _ => false,
+        }
+    }
+
+    /// Generate all legal moves for the piece at `from`.
+    pub fn legal_moves_from(&self, from: Coord) -> Vec<Move> {
+        self.legal_moves()
+            .into_iter()
+            .filter(|mv| mv.from == from)
+            .collect()
+    }
+
+    /// Generate moves following each piece's movement rules.
+    fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let team = self.side_to_move;
+        let occupied = self.occupied();
+        let own = self.by_team[team_index(team)];
+
+        for (from, piece) in self.pieces().filter(|(_, piece)| piece.team == team) {
+            let targets = match piece.kind {
+                ChessPieceType::Pawn => {
+                    self.pawn_moves(from, team, &mut moves);
+                    continue;
+                }
+                ChessPieceType::Knight => bitboard::knight_attacks(from),
+                ChessPieceType::King => {
+                    self.castling_moves(team, &mut moves);
+                    bitboard::king_attacks(from)
+                }
+                ChessPieceType::Rook => bitboard::rook_attacks(from, occupied),
+                ChessPieceType::Bishop => bitboard::bishop_attacks(from, occupied),
+                ChessPieceType::Queen => {
+                    bitboard::rook_attacks(from, occupied)
+                        | bitboard::bishop_attacks(from, occupied)
+                }
+            };
+
+            moves.extend(squares(targets & !own).map(|to| Move::new(from, to)));
+        }
+
+        moves
+    }
+
+    /// Add castling moves for every side `team` still has the right to castle towards.
+    fn castling_moves(&self, team: Team, moves: &mut Vec<Move>) {
+        let rank = team.back_rank();
+        let square = |file: usize| Coord::from_index(rank * 8 + file);
+
+        for side in CastlingSide::iter() {
+            if !self.castling.has(team, side) {
+                continue;
+            }
+
+            let squares = side.squares(team);
+            if self.piece_at(squares.king_from) != Some(Piece::new(team, ChessPieceType::King))
+                || self.piece_at(squares.rook_from) != Some(Piece::new(team, ChessPieceType::Rook))
+            {
+                continue;
+            }
+
+            // Every square between the king and rook must be empty.
+            let (low, high) = min_max(squares.king_from.file(), squares.rook_from.file());
+            if (low + 1..high).any(|file| self.piece_at(square(file)).is_some()) {
+                continue;
+            }
+
+            // The king may not castle out of, through or into check.
+            let (low, high) = min_max(squares.king_from.file(), squares.king_to.file());
+            if (low..=high).any(|file| self.is_attacked(square(file), team.opponent())) {
+                continue;
+            }
+
+            moves.push(Move::new(squares.king_from, squares.king_to));
+        }
+    }
+
+    /// The side being castled towards if `mv` is a castling move in this position.
+    pub fn castling_side(&self, mv: &Move) -> Option<CastlingSide> {
+        let piece = self.piece_at(mv.from)?;
+        if piece.kind != ChessPieceType::King {
+            return None;
+        }
+
+        CastlingSide::iter().find(|side| {
+            let squares = side.squares(piece.team);
+            squares.king_from == mv.from && squares.king_to == mv.to
+        })
+    }
+
+    fn pawn_moves(&self, from: Coord, team: Team, moves: &mut Vec<Move>) {
+        let forward = team.forward();
+        let empty = !self.occupied();
+        let mut targets = 0;
+
+        if let Some(ahead) = from
+            .offset(0, forward)
+            .filter(|ahead| empty & bit(*ahead) != 0)
+        {
+            targets |= bit(ahead);
+
+            // Pawns may advance two squares from their starting rank.
+            let start_rank = (team.back_rank() as i32 + forward) as usize;
+            if from.rank() == start_rank {
+                targets |= ahead
+                    .offset(0, forward)
+                    .map_or(0, |double| bit(double) & empty);
+            }
+        }
+
+        // Pawns capture diagonally forward, including a pawn that just
+        // advanced two squares as if it had only advanced one.
+        let attacks = bitboard::pawn_attacks(team, from);
+        targets |= attacks & self.by_team[team_index(team.opponent())];
+        if let Some(target) = self.en_passant {
+            targets |= attacks & bit(target);
+        }
+
+        for to in squares(targets) {
+            Self::pawn_move(from, to, team, moves);
+        }
+    }
+
+    /// Add a pawn move, expanding it into each promotion when reaching the far rank.
+    fn pawn_move(from: Coord, to: Coord, team: Team, moves: &mut Vec<Move>) {
+        if to.rank() != team.opponent().back_rank() {
+            moves.push(Move::new(from, to));
+            return;
+        }
+
+        for kind in PROMOTIONS {
+            moves.push(Move {
+                from,
+                to,
+                promotion: Some(kind),
+            });
+        }
+    }
+
+    /// Whether moving the piece at `from` to `to` requires choosing a promotion.
+    pub fn is_promotion(&self, from: Coord, to: Coord) -> bool {
+        self.piece_at(from).is_some_and(|piece| {
+            piece.kind == ChessPieceType::Pawn && to.rank() == piece.team.opponent().back_rank()
+        })
+    }
+
+    /// Whether the move takes a piece, including en passant.
+    pub fn is_capture(&self, mv: &Move) -> bool {
+        self.piece_at(mv.to).is_some() || self.en_passant_capture_square(mv).is_some()
+    }
+
+    /// The square of the pawn captured if `mv` is an en passant capture in this position.
+    pub fn en_passant_capture_square(&self, mv: &Move) -> Option<Coord> {
+        let piece = self.piece_at(mv.from)?;
+        if piece.kind != ChessPieceType::Pawn
+            || self.en_passant != Some(mv.to)
+            || mv.from.file() == mv.to.file()
+        {
+            return None;
+        }
+
+        // The captured pawn sits behind the destination, beside the capturing pawn.
+        Some(Coord::from_index(mv.from.rank() * 8 + mv.to.file()))
+    }
+
+    /// Apply a move. The move is expected to be legal for this position.
+    pub fn make_move(&mut self, mv: Move) {
+        let moved = self
+            .piece_at(mv.from)
+            .unwrap_or_else(|| panic!("No piece to move at {}", mv.from));
+        let en_passant_capture = self.en_passant_capture_square(&mv);
+        let captured = self.piece_at(en_passant_capture.unwrap_or(mv.to));
+        let castling_side = self.castling_side(&mv);
+
+        self.history.push(Undo {
+            key: self.hash_key(),
+            mv,
+            moved,
+            captured,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+        });
+
+        self.set_piece(mv.from, None);
+        if let Some(square) = en_passant_capture {
+            self.set_piece(square, None);
+        }
+        let placed = match mv.promotion {
+            Some(kind) => Piece::new(moved.team, kind),
+            None => moved,
+        };
+        self.set_piece(mv.to, Some(placed));
+        self.en_passant = None;
+
+        // Remember the square skipped by a double step so it can be captured en passant.
+        if moved.kind == ChessPieceType::Pawn && mv.from.rank().abs_diff(mv.to.rank()) == 2 {
+            self.en_passant = mv.from.offset(0, moved.team.forward());
+        }
+
+        // Castling also relocates the rook.
+        if let Some(side) = castling_side {
+            let squares = side.squares(moved.team);
+            let rook = self.piece_at(squares.rook_from);
+            self.set_piece(squares.rook_from, None);
+            self.set_piece(squares.rook_to, rook);
+        }
+
+        // Rights are lost once the king or a rook leaves home, or a rook is captured there.
+        let mut castling = self.castling;
+        if moved.kind == ChessPieceType::King {
+            for side in CastlingSide::iter() {
+                castling.revoke(moved.team, side);
+            }
+        }
+        for team in Team::iter() {
+            for side in CastlingSide::iter() {
+                let rook_home = side.squares(team).rook_from;
+                if mv.from == rook_home || mv.to == rook_home {
+                    castling.revoke(team, side);
+                }
+            }
+        }
+        self.set_castling(castling);
+
+        if moved.kind == ChessPieceType::Pawn || captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+
+        if moved.team == Team::Black {
+            self.fullmove_number += 1;
+        }
+
+        self.set_side_to_move(self.side_to_move.opponent());
+    }
+
+    /// Revert the most recent move. Returns the move that was reverted.
+    pub fn unmake_move(&mut self) -> Option<Move> {
+        let undo = self.history.pop()?;
+
+        self.set_side_to_move(self.side_to_move.opponent());
+        if undo.moved.team == Team::Black {
+            self.fullmove_number -= 1;
+        }
+
+        self.set_piece(undo.mv.from, Some(undo.moved));
+        self.set_piece(undo.mv.to, undo.captured);
+
+        if undo.moved.kind == ChessPieceType::Pawn
+            && undo.en_passant == Some(undo.mv.to)
+            && undo.mv.from.file() != undo.mv.to.file()
+        {
+            let square = Coord::from_index(undo.mv.from.rank() * 8 + undo.mv.to.file());
+            self.set_piece(undo.mv.to, None);
+            self.set_piece(square, undo.captured);
+        }
+
+        if undo.moved.kind == ChessPieceType::King {
+            if let Some(side) = CastlingSide::iter().find(|side| {
+                let squares = side.squares(undo.moved.team);
+                squares.king_from == undo.mv.from && squares.king_to == undo.mv.to
+            }) {
+                let squares = side.squares(undo.moved.team);
+                let rook = self.piece_at(squares.rook_to);
+                self.set_piece(squares.rook_to, None);
+                self.set_piece(squares.rook_from, rook);
+            }
+        }
+        self.set_castling(undo.castling);
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+
+        Some(undo.mv)
+    }
+}
+
+fn min_max(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+/// The pieces a pawn may be promoted to.
+pub const PROMOTIONS: [ChessPieceType; 4] = [
+    ChessPieceType::Queen,
+    ChessPieceType::Rook,
+    ChessPieceType::Bishop,
+    ChessPieceType::Knight,
+];
+
+const ROOK_DIRECTIONS: [BoardDir; 4] = [
+    BoardDir::Up,
+    BoardDir::Down,
+    BoardDir::Left,
+    BoardDir::Right,
+];
+
+const BISHOP_DIRECTIONS: [BoardDir; 4] = [
+    BoardDir::UpLeft,
+    BoardDir::UpRight,
+    BoardDir::DownLeft,
+    BoardDir::DownRight,
+];
+
+const KNIGHT_JUMPS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the long algebraic `text` is a legal move in `fen`.
+    fn is_legal(fen: &str, text: &str) -> bool {
+        let mv = Move::from_long_algebraic(text).unwrap();
+        Position::from_fen(fen).unwrap().legal_moves().contains(&mv)
+    }
+
+    #[test]
+    fn pinned_pieces_stay_on_the_pin() {
+        let fen = "4k3/4r3/8/8/8/8/4B3/4K3 w - - 0 1";
+        assert!(!is_legal(fen, "e2d3"));
+        let fen = "4k3/4r3/8/8/8/8/4R3/4K3 w - - 0 1";
+        assert!(is_legal(fen, "e2e5"));
+        assert!(!is_legal(fen, "e2d2"));
+    }
+
+    #[test]
+    fn check_must_be_answered() {
+        let fen = "4k3/8/8/8/8/8/3PPN2/r3K3 w - - 0 1";
+        let mut moves: Vec<String> = Position::from_fen(fen)
+            .unwrap()
+            .legal_moves()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        moves.sort();
+        // The king can't step along the rank, so only the knight can block.
+        assert_eq!(moves, ["f2d1"]);
+    }
+
+    #[test]
+    fn castling_through_or_out_of_check() {
+        let fen = "4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+        assert!(is_legal(fen, "e1g1"));
+        assert!(is_legal(fen, "e1c1"));
+        // Out of check.
+        let fen = "4r1k1/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+        assert!(!is_legal(fen, "e1g1"));
+        assert!(!is_legal(fen, "e1c1"));
+        // Through an attacked square.
+        let fen = "4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+        assert!(!is_legal(fen, "e1g1"));
+        // Into check.
+        let fen = "4k1r1/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+        assert!(!is_legal(fen, "e1g1"));
+        // Only the rook passes b1, so an attack there doesn't matter.
+        let fen = "1r2k3/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+        assert!(is_legal(fen, "e1c1"));
+    }
+
+    #[test]
+    fn castling_rights_are_lost_when_pieces_move() {
+        let mut position = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        for text in ["h1h2", "a8a7"] {
+            position.make_move(position.parse_move(text).unwrap());
+        }
+        assert_eq!(position.to_fen(), "4k2r/r7/8/8/8/8/7R/R3K3 w Qk - 2 2");
+    }
+
+    #[test]
+    fn en_passant_removes_the_passed_pawn() {
+        let mut position = Position::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        let mv = position.parse_move("e5d6").unwrap();
+        assert!(position.is_capture(&mv));
+        position.make_move(mv);
+        assert_eq!(position.to_fen(), "4k3/8/3P4/8/8/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn en_passant_only_on_the_next_move() {
+        let mut position = Position::from_fen("4k3/3p4/8/4P3/8/8/8/4K3 b - - 0 1").unwrap();
+        position.make_move(position.parse_move("d7d5").unwrap());
+        assert!(position.parse_move("e5d6").is_ok());
+        for text in ["e1e2", "e8e7"] {
+            position.make_move(position.parse_move(text).unwrap());
+        }
+        assert!(position.parse_move("e5d6").is_err());
+    }
+
+    #[test]
+    fn en_passant_cannot_expose_the_king_along_the_rank() {
+        // Both pawns leave the fifth rank, opening it to the rook.
+        let fen = "8/8/8/K2pP2r/8/8/8/4k3 w - d6 0 1";
+        assert!(!is_legal(fen, "e5d6"));
+        assert!(is_legal(fen, "e5e6"));
+    }
+
+    #[test]
+    fn promotion_offers_every_piece() {
+        let fen = "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        let mut moves: Vec<String> = Position::from_fen(fen)
+            .unwrap()
+            .legal_moves_from(Coord::from_algebraic("a7").unwrap())
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        moves.sort();
+        assert_eq!(
+            moves,
+            ["a7a8b", "a7a8n", "a7a8q", "a7a8r", "a7b8b", "a7b8n", "a7b8q", "a7b8r"]
+        );
+        assert!(!is_legal(fen, "a7a8"));
+    }
+
+    #[test]
+    fn promotion_places_the_chosen_piece() {
+        let mut position = Position::from_fen("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        position.make_move(position.parse_move("a7b8n").unwrap());
+        assert_eq!(position.to_fen(), "1N2k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        position.unmake_move();
+        assert_eq!(position.to_fen(), "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    /// `fen` after playing the long algebraic `moves`.
+    fn after(fen: &str, moves: &[&str]) -> Position {
+        let mut position = Position::from_fen(fen).unwrap();
+        for text in moves {
+            position.make_move(position.parse_move(text).unwrap());
+        }
+        position
+    }
+
+    #[test]
+    fn fifty_move_rule() {
+        let fen = "4k3/8/8/8/8/8/P7/1R2K3 w - - 99 80";
+        assert_eq!(Position::from_fen(fen).unwrap().outcome(), None);
+        assert_eq!(
+            after(fen, &["b1b2"]).outcome(),
+            Some((GameResult::Draw, GameOverReason::FiftyMoveRule))
+        );
+        // A pawn move starts the count again.
+        assert_eq!(after(fen, &["a2a3"]).halfmove_clock(), 0);
+        assert_eq!(after(fen, &["a2a3"]).outcome(), None);
+        // Checkmate on the hundredth half-move still wins.
+        let fen = "6k1/5ppp/8/8/8/8/8/R3K3 w - - 99 80";
+        assert_eq!(
+            after(fen, &["a1a8"]).outcome(),
+            Some((GameResult::WhiteWins, GameOverReason::Checkmate))
+        );
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let fen = Position::starting().to_fen();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+
+        let once = after(&fen, &shuffle);
+        assert_eq!(once.repetitions(), 2);
+        assert_eq!(once.outcome(), None);
+
+        let twice = after(&fen, &[shuffle, shuffle].concat());
+        assert_eq!(twice.repetitions(), 3);
+        assert_eq!(
+            twice.outcome(),
+            Some((GameResult::Draw, GameOverReason::ThreefoldRepetition))
+        );
+    }
+
+    #[test]
+    fn insufficient_material() {
+        for fen in [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+        ] {
+            let position = Position::from_fen(fen).unwrap();
+            assert!(position.is_insufficient_material(), "{}", fen);
+            assert_eq!(
+                position.outcome(),
+                Some((GameResult::Draw, GameOverReason::InsufficientMaterial))
+            );
+        }
+        for fen in [
+            "4k3/8/8/8/8/8/P7/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1KN2 w - - 0 1",
+        ] {
+            let position = Position::from_fen(fen).unwrap();
+            assert!(!position.is_insufficient_material(), "{}", fen);
+            assert_eq!(position.outcome(), None);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Sets of squares packed into 64-bit masks, and the attack tables the rules
+//! look them up in.
+//!
+//! Bit `n` stands for the square with [Coord::index] `n`. Sliding attacks use
+//! "magic" multipliers that hash the pieces blocking a rook or bishop into an
+//! index of a precomputed table. Rather than embedding known magics they are
+//! found the first time they are needed, by a search seeded with a fixed
+//! value so every run builds the same tables.
+
+use strum::IntoEnumIterator;
+
+use super::{Coord, BISHOP_DIRECTIONS, KNIGHT_JUMPS, ROOK_DIRECTIONS};
+use crate::chess::board_coords::Direction as BoardDir;
+use crate::chess::Team;
+
+pub type Bitboard = u64;
+
+/// The seed for finding magics.
+const MAGIC_SEED: u64 = 0x4D41_4749_4353_4545;
+
+/// How a rook or bishop on one square finds its attacks in the table.
+struct Magic {
+    /// The squares whose occupants can block the piece, excluding the edge of
+    /// the board as pieces there block nothing further.
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+
+    /// Where this square's attacks start in the table.
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+/// Attacks of a sliding piece on every square for every set of blockers.
+struct SliderTable {
+    magics: Vec<Magic>,
+    attacks: Vec<Bitboard>,
+}
+
+impl SliderTable {
+    fn new(directions: &[BoardDir], rng: &mut Rng) -> Self {
+        let mut magics = Vec::with_capacity(64);
+        let mut attacks = Vec::new();
+
+        for square in 0..64 {
+            let mask = ray_attacks(square, 0, directions) & !edges(square);
+            let bits = mask.count_ones();
+
+            // Every subset of the mask paired with the attacks it leads to.
+            let mut blockers = Vec::with_capacity(1 << bits);
+            let mut subset: Bitboard = 0;
+            loop {
+                blockers.push((subset, ray_attacks(square, subset, directions)));
+                subset = subset.wrapping_sub(mask) & mask;
+                if subset == 0 {
+                    break;
+                }
+            }
+
+            let offset = attacks.len();
+            attacks.resize(offset + (1 << bits), 0);
+            let table = &mut attacks[offset..];
+
+            let magic = loop {
+                // Sparse candidates make good magics far more often.
+                let magic = rng.next() & rng.next() & rng.next();
+                if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+                    continue;
+                }
+
+                table.fill(0);
+                let fits = blockers.iter().all(|(occupied, reach)| {
+                    let index = (occupied.wrapping_mul(magic) >> (64 - bits)) as usize;
+                    // No square attacks nothing, so empty entries are unused.
+                    if table[index] == 0 {
+                        table[index] = *reach;
+                    }
+                    table[index] == *reach
+                });
+                if fits {
+                    break magic;
+                }
+            };
+
+            magics.push(Magic {
+                mask,
+                magic,
+                shift: 64 - bits,
+                offset,
+            });
+        }
+
+        Self { magics, attacks }
+    }
+
+    fn attacks(&self, square: usize, occupied: Bitboard) -> Bitboard {
+        self.attacks[self.magics[square].index(occupied)]
+    }
+}
+
+/// The SplitMix64 step, which adds a constant to `value` and scrambles its
+/// bits. Also used to vary the engine's play from one position to another.
+pub fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A SplitMix64 generator, so the magics found are the same every run.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        let value = mix(self.0);
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        value
+    }
+}
+
+struct Tables {
+    knight: [Bitboard; 64],
+    king: [Bitboard; 64],
+
+    /// Squares attacked by a pawn, indexed by team then square.
+    pawn: [[Bitboard; 64]; 2],
+
+    rook: SliderTable,
+    bishop: SliderTable,
+}
+
+impl Tables {
+    fn new() -> Self {
+        let steps = |offsets: &[(i32, i32)], square: usize| {
+            offsets
+                .iter()
+                .filter_map(|(x, y)| Coord::from_index(square).offset(*x, *y))
+                .fold(0, |bb, coord| bb | bit(coord))
+        };
+        let king_steps = BoardDir::iter()
+            .map(|dir| dir.as_coords())
+            .collect::<Vec<_>>();
+
+        let mut rng = Rng(MAGIC_SEED);
+        Self {
+            knight: std::array::from_fn(|square| steps(&KNIGHT_JUMPS, square)),
+            king: std::array::from_fn(|square| steps(&king_steps, square)),
+            pawn: std::array::from_fn(|team| {
+                let forward = team_from_index(team).forward();
+                std::array::from_fn(|square| steps(&[(-1, forward), (1, forward)], square))
+            }),
+            rook: SliderTable::new(&ROOK_DIRECTIONS, &mut rng),
+            bishop: SliderTable::new(&BISHOP_DIRECTIONS, &mut rng),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TABLES: Tables = Tables::new();
+}
+
+/// The set containing only `coord`.
+pub fn bit(coord: Coord) -> Bitboard {
+    1 << coord.index()
+}
+
+/// The squares in a set, from A1 towards H8.
+pub fn squares(mut bb: Bitboard) -> impl Iterator<Item = Coord> {
+    std::iter::from_fn(move || {
+        if bb == 0 {
+            return None;
+        }
+        let index = bb.trailing_zeros() as usize;
+        bb &= bb - 1;
+        Some(Coord::from_index(index))
+    })
+}
+
+/// The index of a team in per-team arrays.
+pub fn team_index(team: Team) -> usize {
+    match team {
+        Team::Black => 0,
+        Team::White => 1,
+    }
+}
+
+fn team_from_index(index: usize) -> Team {
+    match index {
+        0 => Team::Black,
+        _ => Team::White,
+    }
+}
+
+pub fn knight_attacks(square: Coord) -> Bitboard {
+    TABLES.knight[square.index()]
+}
+
+pub fn king_attacks(square: Coord) -> Bitboard {
+    TABLES.king[square.index()]
+}
+
+/// The squares a pawn of `team` on `square` captures on.
+pub fn pawn_attacks(team: Team, square: Coord) -> Bitboard {
+    TABLES.pawn[team_index(team)][square.index()]
+}
+
+pub fn rook_attacks(square: Coord, occupied: Bitboard) -> Bitboard {
+    TABLES.rook.attacks(square.index(), occupied)
+}
+
+pub fn bishop_attacks(square: Coord, occupied: Bitboard) -> Bitboard {
+    TABLES.bishop.attacks(square.index(), occupied)
+}
+
+/// Attacks along `directions` found by walking each ray, used to fill the
+/// magic tables.
+fn ray_attacks(square: usize, occupied: Bitboard, directions: &[BoardDir]) -> Bitboard {
+    let mut attacks = 0;
+    for dir in directions {
+        let (x, y) = dir.as_coords();
+        let mut current = Coord::from_index(square);
+        while let Some(ahead) = current.offset(x, y) {
+            attacks |= bit(ahead);
+            if occupied & bit(ahead) != 0 {
+                break;
+            }
+            current = ahead;
+        }
+    }
+    attacks
+}
+
+/// The edges of the board that can't block a piece on `square`, i.e. all of
+/// them except those `square` is on.
+fn edges(square: usize) -> Bitboard {
+    const RANK_1: Bitboard = 0xFF;
+    const RANK_8: Bitboard = 0xFF << 56;
+    const FILE_A: Bitboard = 0x0101_0101_0101_0101;
+    const FILE_H: Bitboard = FILE_A << 7;
+
+    let coord = Coord::from_index(square);
+    let ranks = (RANK_1 | RANK_8) & !(RANK_1 << (8 * coord.rank()));
+    let files = (FILE_A | FILE_H) & !(FILE_A << coord.file());
+    ranks | files
+}
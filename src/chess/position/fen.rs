@@ -0,0 +1,272 @@
+//! Forsyth–Edwards Notation (FEN) for positions.
+
+use strum::IntoEnumIterator;
+
+use super::{CastlingRights, Coord, Piece, Position};
+use crate::chess::{ChessPieceType, Team};
+
+impl Piece {
+    /// The FEN letter for the piece. White pieces are upper case.
+    pub fn to_fen_char(self) -> char {
+        let c = match self.kind {
+            ChessPieceType::Pawn => 'p',
+            ChessPieceType::Rook => 'r',
+            ChessPieceType::Knight => 'n',
+            ChessPieceType::Bishop => 'b',
+            ChessPieceType::Queen => 'q',
+            ChessPieceType::King => 'k',
+        };
+
+        match self.team {
+            Team::Black => c,
+            Team::White => c.to_ascii_uppercase(),
+        }
+    }
+
+    pub fn from_fen_char(c: char) -> Result<Self, String> {
+        let kind = match c.to_ascii_lowercase() {
+            'p' => ChessPieceType::Pawn,
+            'r' => ChessPieceType::Rook,
+            'n' => ChessPieceType::Knight,
+            'b' => ChessPieceType::Bishop,
+            'q' => ChessPieceType::Queen,
+            'k' => ChessPieceType::King,
+            _ => return Err(format!("Unknown piece `{}`", c)),
+        };
+
+        let team = if c.is_ascii_uppercase() {
+            Team::White
+        } else {
+            Team::Black
+        };
+
+        Ok(Piece::new(team, kind))
+    }
+}
+
+impl Position {
+    /// Parse a position from FEN. The move clocks may be omitted.
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        let fields = fen.split_whitespace().collect::<Vec<_>>();
+        if !(4..=6).contains(&fields.len()) {
+            return Err(format!(
+                "Expected 4 to 6 fields in FEN but found {}: `{}`",
+                fields.len(),
+                fen
+            ));
+        }
+
+        let mut position = Position::empty();
+
+        // Piece placement, from the eighth rank down.
+        let ranks = fields[0].split('/').collect::<Vec<_>>();
+        if ranks.len() != 8 {
+            return Err(format!("Expected 8 ranks but found {}", ranks.len()));
+        }
+        for (row, rank_text) in ranks.iter().enumerate() {
+            let rank = 7 - row;
+            let mut file = 0;
+            for c in rank_text.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    file += empty as usize;
+                    continue;
+                }
+
+                if file >= 8 {
+                    return Err(format!("Rank {} has more than 8 files", rank + 1));
+                }
+                let piece = Piece::from_fen_char(c)?;
+                position.set_piece(Coord::from_index(rank * 8 + file), Some(piece));
+                file += 1;
+            }
+            if file != 8 {
+                return Err(format!("Rank {} does not have 8 files", rank + 1));
+            }
+        }
+
+        for team in Team::iter() {
+            let kings = position
+                .pieces()
+                .filter(|(_, piece)| *piece == Piece::new(team, ChessPieceType::King))
+                .count();
+            if kings != 1 {
+                return Err(format!("{} must have exactly one king", team));
+            }
+        }
+
+        if let Some((coord, _)) = position
+            .pieces()
+            .find(|(coord, piece)| piece.kind == ChessPieceType::Pawn && coord.rank() % 7 == 0)
+        {
+            return Err(format!(
+                "Pawns cannot stand on the first or last rank, at {}",
+                coord
+            ));
+        }
+
+        position.set_side_to_move(match fields[1] {
+            "w" => Team::White,
+            "b" => Team::Black,
+            other => return Err(format!("Unknown side to move `{}`", other)),
+        });
+
+        let mut castling = CastlingRights::none();
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                match c {
+                    'K' => castling.white_king_side = true,
+                    'Q' => castling.white_queen_side = true,
+                    'k' => castling.black_king_side = true,
+                    'q' => castling.black_queen_side = true,
+                    _ => return Err(format!("Unknown castling right `{}`", c)),
+                }
+            }
+        }
+        position.set_castling(castling);
+
+        // The side that just moved can't have left its king in check.
+        let side_to_move = position.side_to_move();
+        if position.is_in_check(side_to_move.opponent()) {
+            return Err(format!(
+                "{} is in check but it is not their move",
+                side_to_move.opponent()
+            ));
+        }
+
+        position.en_passant = match fields[3] {
+            "-" => None,
+            square => {
+                // Only a pawn that just moved two squares past it can be
+                // taken en passant.
+                let coord = Coord::from_algebraic(square)?;
+                let rank = match side_to_move {
+                    Team::White => 5,
+                    Team::Black => 2,
+                };
+                let pushed = Piece::new(side_to_move.opponent(), ChessPieceType::Pawn);
+                let pawn = coord.offset(0, side_to_move.opponent().forward());
+                if coord.rank() != rank
+                    || position.piece_at(coord).is_some()
+                    || pawn.and_then(|pawn| position.piece_at(pawn)) != Some(pushed)
+                {
+                    return Err(format!("Invalid en passant square `{}`", square));
+                }
+                Some(coord)
+            }
+        };
+
+        if let Some(clock) = fields.get(4) {
+            position.halfmove_clock = clock
+                .parse()
+                .map_err(|_| format!("Invalid halfmove clock `{}`", clock))?;
+        }
+
+        if let Some(number) = fields.get(5) {
+            position.fullmove_number = number
+                .parse()
+                .map_err(|_| format!("Invalid fullmove number `{}`", number))?;
+        }
+
+        Ok(position)
+    }
+
+    /// Describe the position in FEN.
+    pub fn to_fen(&self) -> String {
+        let mut placement = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.piece_at(Coord::from_index(rank * 8 + file)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            placement.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        placement.push(piece.to_fen_char());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                placement.push('/');
+            }
+        }
+
+        let side_to_move = match self.side_to_move {
+            Team::White => "w",
+            Team::Black => "b",
+        };
+
+        let mut castling = String::new();
+        for (right, c) in [
+            (self.castling.white_king_side, 'K'),
+            (self.castling.white_queen_side, 'Q'),
+            (self.castling.black_king_side, 'k'),
+            (self.castling.black_queen_side, 'q'),
+        ] {
+            if right {
+                castling.push(c);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some(square) => square.algebraic(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            side_to_move,
+            castling,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+            "rnbqkbnr/pppp1ppp/8/3Pp3/8/8/PPP1PPPP/RNBQKBNR w KQkq e6 0 3",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn en_passant_needs_a_pushed_pawn() {
+        // No pawn has just passed e4.
+        assert!(Position::from_fen("k7/8/8/8/8/3P4/4N3/4K3 w - e4 0 1").is_err());
+        assert!(Position::from_fen("k7/8/8/8/8/8/8/4K3 w - e6 0 1").is_err());
+        // The square the pawn passed must be empty.
+        assert!(Position::from_fen("k7/8/4n3/4p3/8/8/8/4K3 w - e6 0 1").is_err());
+        // It is on the wrong rank for the side to move.
+        assert!(Position::from_fen("k7/8/8/8/4P3/8/8/4K3 w - e3 0 1").is_err());
+    }
+
+    #[test]
+    fn side_not_to_move_cannot_be_in_check() {
+        assert!(Position::from_fen("k6R/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(Position::from_fen("k6R/8/8/8/8/8/8/4K3 b - - 0 1").is_ok());
+    }
+
+    #[test]
+    fn pawns_cannot_be_on_back_ranks() {
+        assert!(Position::from_fen("k2P4/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(Position::from_fen("k7/8/8/8/8/8/8/3pK3 w - - 0 1").is_err());
+    }
+}
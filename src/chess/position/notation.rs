@@ -0,0 +1,317 @@
+//! Reading and writing moves in Standard Algebraic Notation (SAN) and the
+//! long algebraic notation used by UCI.
+
+use std::fmt;
+
+use super::{CastlingSide, Coord, Move, Piece, Position};
+use crate::chess::{ChessPieceType, Team};
+
+/// The upper case letter SAN uses for a kind of piece.
+pub fn piece_letter(kind: ChessPieceType) -> char {
+    Piece::new(Team::White, kind).to_fen_char()
+}
+
+/// The kind of piece for an upper case SAN letter.
+fn piece_from_letter(letter: char) -> Option<ChessPieceType> {
+    match letter {
+        'N' | 'B' | 'R' | 'Q' | 'K' => Piece::from_fen_char(letter).ok().map(|piece| piece.kind),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Move {
+    /// Long algebraic notation, e.g. `e2e4`, `e1g1` for castling or `e7e8q`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.from.algebraic(), self.to.algebraic())?;
+        if let Some(kind) = self.promotion {
+            write!(f, "{}", Piece::new(Team::Black, kind).to_fen_char())?;
+        }
+        Ok(())
+    }
+}
+
+impl Move {
+    /// Parse a move in long algebraic notation. Whether the move is legal is
+    /// not checked.
+    pub fn from_long_algebraic(text: &str) -> Result<Self, String> {
+        if !text.is_ascii() || !(4..=5).contains(&text.len()) {
+            return Err(format!("Invalid long algebraic move `{}`", text));
+        }
+
+        let from = Coord::from_algebraic(&text[0..2])?;
+        let to = Coord::from_algebraic(&text[2..4])?;
+        let promotion = match text[4..].chars().next() {
+            Some(c @ ('n' | 'b' | 'r' | 'q')) => Some(Piece::from_fen_char(c)?.kind),
+            Some(c) => return Err(format!("Invalid promotion `{}` in `{}`", c, text)),
+            None => None,
+        };
+
+        Ok(Self {
+            from,
+            to,
+            promotion,
+        })
+    }
+}
+
+impl Position {
+    /// Find the legal move described by either SAN or long algebraic notation.
+    pub fn parse_move(&self, text: &str) -> Result<Move, String> {
+        match Move::from_long_algebraic(text) {
+            Ok(mv) if self.legal_moves().contains(&mv) => Ok(mv),
+            Ok(_) => Err(format!("`{}` is not a legal move", text)),
+            Err(_) => self.parse_san(text),
+        }
+    }
+
+    /// Describe a legal move in SAN, e.g. `Nbd7`, `exd5`, `e8=Q+` or `O-O-O#`.
+    pub fn to_san(&self, mv: &Move) -> String {
+        let piece = self
+            .piece_at(mv.from)
+            .unwrap_or_else(|| panic!("No piece to move at {}", mv.from));
+
+        let mut san = match self.castling_side(mv) {
+            Some(CastlingSide::KingSide) => "O-O".to_string(),
+            Some(CastlingSide::QueenSide) => "O-O-O".to_string(),
+            None => {
+                let is_capture = self.is_capture(mv);
+
+                let mut san = String::new();
+                if piece.kind == ChessPieceType::Pawn {
+                    if is_capture {
+                        san.push_str(&mv.from.algebraic()[..1]);
+                    }
+                } else {
+                    san.push(piece_letter(piece.kind));
+                    san.push_str(&self.disambiguation(mv, piece.kind));
+                }
+
+                if is_capture {
+                    san.push('x');
+                }
+                san.push_str(&mv.to.algebraic());
+
+                if let Some(kind) = mv.promotion {
+                    san.push('=');
+                    san.push(piece_letter(kind));
+                }
+
+                san
+            }
+        };
+
+        let mut after = self.clone();
+        after.make_move(*mv);
+        if after.is_in_check(after.side_to_move()) {
+            san.push(if after.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+
+        san
+    }
+
+    /// Find the legal move described by `san`. Check, mate and annotation
+    /// suffixes such as `+`, `#` or `!?` are ignored.
+    pub fn parse_san(&self, san: &str) -> Result<Move, String> {
+        let text = san.trim_end_matches(['+', '#', '!', '?']);
+        let legal_moves = self.legal_moves();
+
+        // Castling is written with letter O, though zeros are common too.
+        let castling_side = match text {
+            "O-O" | "0-0" => Some(CastlingSide::KingSide),
+            "O-O-O" | "0-0-0" => Some(CastlingSide::QueenSide),
+            _ => None,
+        };
+        if let Some(side) = castling_side {
+            return legal_moves
+                .into_iter()
+                .find(|mv| self.castling_side(mv) == Some(side))
+                .ok_or_else(|| format!("Castling `{}` is not legal", san));
+        }
+
+        // Split off any promotion, written either `e8=Q` or `e8Q`.
+        let (text, promotion) = match text.split_once('=') {
+            Some((text, piece)) => {
+                let mut letters = piece.chars();
+                match (letters.next().and_then(piece_from_letter), letters.next()) {
+                    (Some(kind), None) => (text, Some(kind)),
+                    _ => return Err(format!("Invalid promotion in `{}`", san)),
+                }
+            }
+            None => match text.chars().last().and_then(piece_from_letter) {
+                Some(kind) if text.len() > 2 => (&text[..text.len() - 1], Some(kind)),
+                _ => (text, None),
+            },
+        };
+
+        let (kind, text) = match text.chars().next().and_then(piece_from_letter) {
+            Some(kind) => (kind, &text[1..]),
+            None => (ChessPieceType::Pawn, text),
+        };
+
+        if text.len() < 2 || !text.is_char_boundary(text.len() - 2) {
+            return Err(format!("Invalid move `{}`", san));
+        }
+        let (hint, destination) = text.split_at(text.len() - 2);
+        let to = Coord::from_algebraic(destination)?;
+
+        // Whatever is left narrows down where the piece came from.
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in hint.chars() {
+            match c {
+                'a'..='h' => from_file = Some(c as usize - 'a' as usize),
+                '1'..='8' => from_rank = Some(c as usize - '1' as usize),
+                'x' | '-' => {}
+                _ => return Err(format!("Invalid move `{}`", san)),
+            }
+        }
+
+        let candidates = legal_moves
+            .into_iter()
+            .filter(|mv| {
+                mv.to == to
+                    && mv.promotion == promotion
+                    && self.piece_at(mv.from).map(|piece| piece.kind) == Some(kind)
+                    && from_file.is_none_or(|file| mv.from.file() == file)
+                    && from_rank.is_none_or(|rank| mv.from.rank() == rank)
+            })
+            .collect::<Vec<_>>();
+
+        match candidates.as_slice() {
+            [mv] => Ok(*mv),
+            [] => Err(format!("`{}` is not a legal move", san)),
+            _ => Err(format!("`{}` is ambiguous", san)),
+        }
+    }
+
+    /// The fewest characters of the origin square needed to tell `mv` apart
+    /// from moves of other pieces of the same kind to the same square.
+    fn disambiguation(&self, mv: &Move, kind: ChessPieceType) -> String {
+        let others = self
+            .legal_moves()
+            .into_iter()
+            .filter(|other| {
+                other.to == mv.to
+                    && other.from != mv.from
+                    && self.piece_at(other.from).map(|piece| piece.kind) == Some(kind)
+            })
+            .collect::<Vec<_>>();
+
+        let from = mv.from.algebraic();
+        if others.is_empty() {
+            String::new()
+        } else if others
+            .iter()
+            .all(|other| other.from.file() != mv.from.file())
+        {
+            from[..1].to_string()
+        } else if others
+            .iter()
+            .all(|other| other.from.rank() != mv.from.rank())
+        {
+            from[1..].to_string()
+        } else {
+            from
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SAN of the move `from` `to` in `fen`.
+    fn san(fen: &str, from: &str, to: &str) -> String {
+        let position = Position::from_fen(fen).unwrap();
+        let mv = Move::new(
+            Coord::from_algebraic(from).unwrap(),
+            Coord::from_algebraic(to).unwrap(),
+        );
+        assert!(position.legal_moves().contains(&mv), "{} is not legal", mv);
+        position.to_san(&mv)
+    }
+
+    #[test]
+    fn disambiguation_by_file() {
+        let fen = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+        assert_eq!(san(fen, "b1", "d2"), "Nbd2");
+        assert_eq!(san(fen, "f1", "d2"), "Nfd2");
+        assert_eq!(san(fen, "b1", "c3"), "Nc3");
+    }
+
+    #[test]
+    fn disambiguation_by_rank() {
+        let fen = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1", "a3"), "R1a3");
+        assert_eq!(san(fen, "a5", "a3"), "R5a3");
+    }
+
+    #[test]
+    fn disambiguation_by_file_and_rank() {
+        let fen = "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1";
+        assert_eq!(san(fen, "a1", "b2"), "Qa1b2");
+        assert_eq!(san(fen, "c1", "b2"), "Qcb2");
+        assert_eq!(san(fen, "a3", "b2"), "Q3b2");
+    }
+
+    #[test]
+    fn check_and_mate_suffixes() {
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1", "a8"), "Ra8+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1", "a8"), "Ra8#");
+        assert_eq!(san("4k3/8/8/8/8/8/8/4K2R w K - 0 1", "e1", "g1"), "O-O");
+    }
+
+    #[test]
+    fn long_algebraic_round_trips() {
+        for text in ["e2e4", "g8f6", "e7e8q", "a2a1n", "e1g1", "e8c8"] {
+            assert_eq!(Move::from_long_algebraic(text).unwrap().to_string(), text);
+        }
+
+        let promotion = Move::from_long_algebraic("e7e8q").unwrap();
+        assert_eq!(promotion.promotion, Some(ChessPieceType::Queen));
+
+        for text in ["e2e", "e2e4e5", "e2e9", "i2i4", "e7e8k", "e7e8Q"] {
+            assert!(Move::from_long_algebraic(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_move_takes_either_notation() {
+        let position = Position::from_fen("r3k3/1P6/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+
+        // Castling is the king's move in long algebraic notation.
+        let castle = position.parse_move("e1g1").unwrap();
+        assert_eq!(
+            position.castling_side(&castle),
+            Some(CastlingSide::KingSide)
+        );
+        assert_eq!(position.parse_move("O-O").unwrap(), castle);
+        assert_eq!(castle.to_string(), "e1g1");
+
+        let promotion = position.parse_move("b7a8q").unwrap();
+        assert_eq!(position.parse_move("bxa8=Q+").unwrap(), promotion);
+        assert_eq!(
+            position.parse_move("b7b8n").unwrap().promotion,
+            Some(ChessPieceType::Knight)
+        );
+
+        // Well formed but illegal moves aren't read as SAN instead.
+        assert!(position.parse_move("e1e3").is_err());
+        assert!(position.parse_move("b7a8").is_err());
+    }
+
+    #[test]
+    fn san_round_trips() {
+        let position = Position::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        for mv in position.legal_moves() {
+            assert_eq!(position.parse_san(&position.to_san(&mv)), Ok(mv));
+        }
+    }
+}
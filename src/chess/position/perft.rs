@@ -0,0 +1,132 @@
+//! Counting the leaves of the move tree to check move generation against
+//! numbers known to be correct.
+
+use super::{Move, Position};
+
+impl Position {
+    /// The number of move sequences of length `depth` from this position.
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        moves
+            .into_iter()
+            .map(|mv| {
+                self.make_move(mv);
+                let nodes = self.perft(depth - 1);
+                self.unmake_move();
+                nodes
+            })
+            .sum()
+    }
+
+    /// [Position::perft] split by the first move, to narrow down which move
+    /// leads to a wrong count.
+    pub fn divide(&self, depth: u32) -> Vec<(Move, u64)> {
+        let mut position = self.clone();
+        self.legal_moves()
+            .into_iter()
+            .map(|mv| {
+                position.make_move(mv);
+                let nodes = position.perft(depth.saturating_sub(1));
+                position.unmake_move();
+                (mv, nodes)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check `position` against its known counts, starting at depth 1.
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let mut position = Position::from_fen(fen).unwrap();
+        for (depth, expected) in (1..).zip(expected) {
+            assert_eq!(
+                position.perft(depth),
+                *expected,
+                "perft({}) of {}",
+                depth,
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn starting_position() {
+        assert_perft(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8_902, 197_281],
+        );
+    }
+
+    /// Castling, en passant and promotions all in one position.
+    #[test]
+    fn kiwipete() {
+        assert_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2_039, 97_862],
+        );
+    }
+
+    /// An endgame full of en passant pins and discovered checks.
+    #[test]
+    fn rook_endgame() {
+        assert_perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2_812, 43_238],
+        );
+    }
+
+    #[test]
+    fn promotions_and_checks() {
+        assert_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9_467],
+        );
+    }
+
+    /// The same position with colours swapped should give the same counts.
+    #[test]
+    fn promotions_and_checks_mirrored() {
+        assert_perft(
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            &[6, 264, 9_467],
+        );
+    }
+
+    #[test]
+    fn underpromotion_to_check() {
+        assert_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1_486, 62_379],
+        );
+    }
+
+    #[test]
+    fn middle_game() {
+        assert_perft(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2_079, 89_890],
+        );
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut position = Position::starting();
+        let divide = position.divide(3);
+        assert_eq!(divide.len(), 20);
+        assert_eq!(
+            divide.iter().map(|(_, nodes)| nodes).sum::<u64>(),
+            position.perft(3)
+        );
+    }
+}
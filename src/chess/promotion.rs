@@ -0,0 +1,115 @@
+//! Asking the player which piece a pawn is promoted to.
+
+use bevy::prelude::*;
+
+use super::position::PROMOTIONS;
+use super::PieceMoveEvent;
+
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+
+#[derive(Component)]
+pub struct PromotionButton(super::ChessPieceType);
+
+/// A pawn move waiting on the player to choose what the pawn becomes.
+#[derive(Resource, Default)]
+pub struct PendingPromotion {
+    movement: Option<PieceMoveEvent>,
+
+    /// The root of the picker UI.
+    picker: Option<Entity>,
+}
+
+impl PendingPromotion {
+    pub fn is_pending(&self) -> bool {
+        self.movement.is_some()
+    }
+
+    /// Hold on to `movement` and show a picker for the promotion piece.
+    pub fn request(&mut self, commands: &mut Commands, movement: &PieceMoveEvent) {
+        if let Some(picker) = self.picker.take() {
+            commands.entity(picker).despawn();
+        }
+
+        let picker = commands
+            .spawn(Node {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(10.),
+                ..default()
+            })
+            .with_children(|parent| {
+                for kind in PROMOTIONS {
+                    parent.spawn((
+                        Button,
+                        PromotionButton(kind),
+                        Node {
+                            width: Val::Px(120.),
+                            height: Val::Px(50.),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        BackgroundColor(NORMAL_BUTTON),
+                        children![(
+                            Text::new(kind.to_string()),
+                            TextFont {
+                                font_size: 24.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                        )],
+                    ));
+                }
+            })
+            .id();
+
+        self.movement = Some(movement.clone());
+        self.picker = Some(picker);
+    }
+
+    /// Submit the held move once a promotion piece has been chosen, or drop
+    /// it on "Escape" or a right click. "Escape" is ours while the picker is
+    /// open; `HistoryView` only uses it otherwise.
+    pub fn on_update(
+        mut commands: Commands,
+        mut pending: ResMut<PendingPromotion>,
+        interaction_query: Query<(&Interaction, &PromotionButton), Changed<Interaction>>,
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        mouse_buttons: Res<ButtonInput<MouseButton>>,
+        mut writer: EventWriter<PieceMoveEvent>,
+    ) {
+        if !pending.is_pending() {
+            return;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Escape)
+            || mouse_buttons.just_pressed(MouseButton::Right)
+        {
+            if let Some(movement) = pending.movement.take() {
+                println!("Cancelled movement: {}", movement);
+            }
+            if let Some(picker) = pending.picker.take() {
+                commands.entity(picker).despawn();
+            }
+            return;
+        }
+
+        for (interaction, button) in &interaction_query {
+            if *interaction != Interaction::Pressed {
+                continue;
+            }
+
+            if let Some(mut movement) = pending.movement.take() {
+                movement.promotion = Some(button.0);
+                println!("Submitting movement: {}", movement);
+                writer.write(movement);
+            }
+
+            if let Some(picker) = pending.picker.take() {
+                commands.entity(picker).despawn();
+            }
+        }
+    }
+}
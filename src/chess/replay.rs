@@ -0,0 +1,118 @@
+//! Stepping through a game loaded from PGN.
+
+use bevy::prelude::*;
+
+use super::history::MovedFlags;
+use super::pgn::PgnGame;
+use super::{
+    ActiveTeam, AssetLibrary, ChessBoard, ChessPiece, GameHistory, HistoryView, Move,
+    PieceSelection, Position,
+};
+
+/// A recorded game shown on the board one ply at a time.
+#[derive(Resource)]
+pub struct Replay {
+    starting_position: Position,
+    moves: Vec<Move>,
+
+    /// How many moves of the game the board is currently showing.
+    ply: usize,
+}
+
+impl Replay {
+    /// Load game `number` (counting from 1) out of the PGN file at `path`.
+    pub fn load(path: &str, number: usize) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read `{}`: {}", path, e))?;
+        let games = PgnGame::parse_all(&text)?;
+        let game = number
+            .checked_sub(1)
+            .and_then(|index| games.get(index))
+            .ok_or_else(|| format!("`{}` has {} games, not {}", path, games.len(), number))?;
+        let moves = game
+            .resolve_moves()
+            .map_err(|e| format!("Game {}: {}", number, e))?;
+
+        println!(
+            "Replaying game {} of {}: {} vs {} ({} moves)",
+            number,
+            games.len(),
+            game.tag("White").unwrap_or("?"),
+            game.tag("Black").unwrap_or("?"),
+            moves.len()
+        );
+
+        Ok(Self {
+            starting_position: game.starting_position.clone(),
+            moves,
+            ply: 0,
+        })
+    }
+
+    pub fn starting_position(&self) -> &Position {
+        &self.starting_position
+    }
+
+    /// Step through the game with "," and ".", or jump to either end with
+    /// "Home" and "End".
+    #[allow(clippy::too_many_arguments)]
+    pub fn on_update(
+        mut commands: Commands,
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        mut replay: ResMut<Replay>,
+        mut boards_query: Query<(Entity, &mut ChessBoard, &Transform), Without<ChessPiece>>,
+        pieces_query: Query<&ChessPiece>,
+        asset_library: Res<AssetLibrary>,
+        gltf_assets: Res<Assets<Gltf>>,
+        mut active_team: ResMut<ActiveTeam>,
+        mut selection: ResMut<PieceSelection>,
+        mut history: ResMut<GameHistory>,
+    ) {
+        let ply = if keyboard_input.just_pressed(KeyCode::Period) {
+            (replay.ply + 1).min(replay.moves.len())
+        } else if keyboard_input.just_pressed(KeyCode::Comma) {
+            replay.ply.saturating_sub(1)
+        } else if keyboard_input.just_pressed(KeyCode::Home) {
+            0
+        } else if keyboard_input.just_pressed(KeyCode::End) {
+            replay.moves.len()
+        } else {
+            return;
+        };
+
+        if ply == replay.ply {
+            return;
+        }
+        replay.ply = ply;
+        commands.remove_resource::<HistoryView>();
+
+        // Keep the history in step so the shown game can be saved again.
+        *history = GameHistory::new(replay.starting_position.clone());
+        let mut position = replay.starting_position.clone();
+        for mv in &replay.moves[..ply] {
+            history.record(&position, *mv, MovedFlags::default());
+            position.make_move(*mv);
+        }
+
+        for (board_entity, mut board, board_transform) in &mut boards_query {
+            board.show_position(
+                board_entity,
+                &position,
+                &mut commands,
+                &pieces_query,
+                &asset_library,
+                &gltf_assets,
+                board_transform,
+            );
+        }
+
+        active_team.0 = position.side_to_move();
+        selection.piece = None;
+        println!(
+            "Ply {} of {}: {}",
+            ply,
+            replay.moves.len(),
+            position.to_fen()
+        );
+    }
+}
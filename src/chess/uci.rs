@@ -0,0 +1,402 @@
+//! Running the engine under the Universal Chess Interface (UCI) protocol, so
+//! it can be used from chess GUIs and other engines' tooling.
+//!
+//! Commands are read from stdin and responses written to stdout. Searches run
+//! on their own thread so `stop` and `isready` are answered while thinking.
+
+use std::fmt::Display;
+use std::io::BufRead;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use strum::IntoEnumIterator;
+
+use super::engine::{BackgroundSearch, Difficulty, Engine, SearchLimits};
+use super::{Position, Team};
+
+/// How long to think about a move given what's left on the clock.
+pub(super) fn allocate_time(
+    time_left: Duration,
+    increment: Duration,
+    moves_to_go: u32,
+) -> Duration {
+    let available = time_left.saturating_sub(MOVE_OVERHEAD);
+    (available / moves_to_go + increment * 3 / 4).min(available / 2)
+}
+
+/// Time kept in reserve so moves aren't sent after the clock runs out.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+/// The number of moves assumed to be left when the GUI doesn't say.
+pub(super) const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// Where replies to the interface go: stdout, or a channel in tests.
+#[derive(Clone, Default)]
+pub(super) struct Output(Option<Sender<String>>);
+
+impl Output {
+    /// Output that can be read back from the returned channel.
+    #[cfg(test)]
+    pub(super) fn channel() -> (Self, std::sync::mpsc::Receiver<String>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        (Self(Some(sender)), receiver)
+    }
+
+    pub(super) fn line(&self, line: impl Display) {
+        match &self.0 {
+            Some(sender) => {
+                let _ = sender.send(line.to_string());
+            }
+            None => println!("{}", line),
+        }
+    }
+}
+
+struct Uci {
+    output: Output,
+    engine: Arc<Mutex<Engine>>,
+    position: Position,
+    difficulty: Difficulty,
+    search: Option<BackgroundSearch>,
+}
+
+/// Speak UCI on stdin and stdout until `quit` or the end of input.
+pub fn run_uci() {
+    let mut uci = Uci::new(Output::default());
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !uci.handle(&line) {
+            break;
+        }
+    }
+
+    uci.stop();
+}
+
+impl Uci {
+    fn new(output: Output) -> Self {
+        Self {
+            output,
+            engine: Arc::new(Mutex::new(Engine::default())),
+            position: Position::starting(),
+            difficulty: Difficulty::Expert,
+            search: None,
+        }
+    }
+
+    /// Answer a UCI command. Returns `false` once told to `quit`.
+    fn handle(&mut self, line: &str) -> bool {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let Some((command, args)) = tokens.split_first() else {
+            return true;
+        };
+
+        match *command {
+            "uci" => {
+                self.output.line("id name Chess");
+                self.output.line("id author The Chess authors");
+                let levels = Difficulty::iter()
+                    .map(|difficulty| format!(" var {}", difficulty))
+                    .collect::<String>();
+                self.output.line(format_args!(
+                    "option name Difficulty type combo default {}{}",
+                    Difficulty::Expert,
+                    levels
+                ));
+                self.output.line("uciok");
+            }
+            "isready" => self.output.line("readyok"),
+            "ucinewgame" => {
+                self.stop();
+                self.engine = Arc::new(Mutex::new(Engine::default()));
+                self.position = Position::starting();
+            }
+            "setoption" => self.set_option(args),
+            "position" => {
+                self.stop();
+                if let Err(e) = self.set_position(args) {
+                    self.output.line(format_args!("info string {}", e));
+                }
+            }
+            "go" => self.go(args),
+            "stop" => self.stop(),
+            "quit" => return false,
+            // GUIs may send commands this engine has no use for.
+            _ => self
+                .output
+                .line(format_args!("info string Unknown command `{}`", command)),
+        }
+
+        true
+    }
+
+    /// Handle `setoption name <name> value <value>`.
+    fn set_option(&mut self, args: &[&str]) {
+        let text = args.join(" ");
+        let Some((name, value)) = text
+            .strip_prefix("name ")
+            .and_then(|text| text.split_once(" value "))
+        else {
+            self.output
+                .line("info string Expected `setoption name <name> value <value>`");
+            return;
+        };
+
+        match name.trim() {
+            "Difficulty" => match value.trim().parse() {
+                Ok(difficulty) => self.difficulty = difficulty,
+                Err(_) => self.output.line(format_args!(
+                    "info string Unknown difficulty `{}`",
+                    value.trim()
+                )),
+            },
+            name => self
+                .output
+                .line(format_args!("info string Unknown option `{}`", name)),
+        }
+    }
+
+    /// Handle `position [startpos | fen <fen>] [moves <move>...]`.
+    fn set_position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_index = args.iter().position(|arg| *arg == "moves");
+        let (setup, moves) = match moves_index {
+            Some(index) => (&args[..index], &args[index + 1..]),
+            None => (args, &[][..]),
+        };
+
+        let mut position = match setup.split_first() {
+            Some((&"startpos", _)) => Position::starting(),
+            Some((&"fen", fen)) => Position::from_fen(&fen.join(" "))?,
+            _ => return Err("Expected `startpos` or `fen`".to_string()),
+        };
+
+        for text in moves {
+            let mv = position.parse_move(text)?;
+            position.make_move(mv);
+        }
+
+        self.position = position;
+        Ok(())
+    }
+
+    /// Handle `go` by starting a search on another thread.
+    fn go(&mut self, args: &[&str]) {
+        self.stop();
+
+        let mut limits = self.difficulty.limits();
+        let mut infinite = false;
+        let mut time_left = None;
+        let mut increment = Duration::ZERO;
+        let mut moves_to_go = DEFAULT_MOVES_TO_GO;
+
+        // Any limits the GUI gives replace those of the difficulty.
+        let (time_key, increment_key) = match self.position.side_to_move() {
+            Team::White => ("wtime", "winc"),
+            Team::Black => ("btime", "binc"),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().and_then(|value| value.parse::<u64>().ok());
+            match *arg {
+                "depth" => {
+                    limits.depth = value().unwrap_or(1) as u32;
+                    limits.nodes = None;
+                    limits.time = None;
+                }
+                "nodes" => {
+                    limits.nodes = value();
+                    limits.time = None;
+                }
+                "movetime" => {
+                    limits.time = value().map(Duration::from_millis);
+                    limits.nodes = None;
+                }
+                "movestogo" => moves_to_go = value().unwrap_or(1).max(1) as u32,
+                "infinite" => infinite = true,
+                key if key == time_key => time_left = value().map(Duration::from_millis),
+                key if key == increment_key => {
+                    increment = value().map(Duration::from_millis).unwrap_or_default()
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(time_left) = time_left {
+            limits.time = Some(allocate_time(time_left, increment, moves_to_go));
+            limits.nodes = None;
+        }
+        if infinite {
+            limits = SearchLimits {
+                inaccuracy: limits.inaccuracy,
+                nodes: None,
+                time: None,
+                ..SearchLimits::default()
+            };
+        }
+
+        let engine = self.engine.clone();
+        let position = self.position.clone();
+        let output = self.output.clone();
+
+        self.search = Some(BackgroundSearch::spawn(move |stop| {
+            let mut engine = engine.lock().unwrap();
+            let best_move = engine.search(&position, &limits, stop, |info| {
+                output.line(format_args!("info {}", info))
+            });
+
+            // Infinite searches only answer once told to stop.
+            while infinite && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            match best_move {
+                Some(mv) => output.line(format_args!("bestmove {}", mv)),
+                None => output.line("bestmove 0000"),
+            }
+        }));
+    }
+
+    /// End any running search, waiting for it to report its move.
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+
+    use super::*;
+
+    fn uci() -> (Uci, Receiver<String>) {
+        let (output, receiver) = Output::channel();
+        (Uci::new(output), receiver)
+    }
+
+    /// Feed `lines` to the engine and collect what it answers, waiting for
+    /// any search to finish.
+    fn run(uci: &mut Uci, receiver: &Receiver<String>, lines: &[&str]) -> Vec<String> {
+        for line in lines {
+            assert!(uci.handle(line));
+        }
+
+        let mut output = Vec::new();
+        if uci.search.is_some() {
+            while let Ok(line) = receiver.recv_timeout(Duration::from_secs(10)) {
+                output.push(line);
+                if output.last().unwrap().starts_with("bestmove") {
+                    break;
+                }
+            }
+            uci.stop();
+        }
+        output.extend(receiver.try_iter());
+        output
+    }
+
+    #[test]
+    fn handshake() {
+        let (mut uci, receiver) = uci();
+        let output = run(&mut uci, &receiver, &["uci", "isready"]);
+        assert_eq!(output[0], "id name Chess");
+        assert!(output[2].starts_with("option name Difficulty type combo default Expert"));
+        assert_eq!(output[3..], ["uciok", "readyok"]);
+    }
+
+    #[test]
+    fn position_from_start_with_moves() {
+        let (mut uci, receiver) = uci();
+        let output = run(
+            &mut uci,
+            &receiver,
+            &["position startpos moves e2e4 c7c5 g1f3"],
+        );
+        assert!(output.is_empty());
+        assert_eq!(
+            uci.position.to_fen(),
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+    }
+
+    #[test]
+    fn position_from_fen_with_moves() {
+        let (mut uci, receiver) = uci();
+        let output = run(
+            &mut uci,
+            &receiver,
+            &["position fen 4k3/P7/8/8/8/8/8/4K3 w - - 0 1 moves a7a8q e8d7"],
+        );
+        assert!(output.is_empty());
+        assert_eq!(uci.position.to_fen(), "Q7/3k4/8/8/8/8/8/4K3 w - - 1 2");
+    }
+
+    #[test]
+    fn bad_position_is_reported_and_ignored() {
+        let (mut uci, receiver) = uci();
+        let output = run(&mut uci, &receiver, &["position startpos moves e2e5"]);
+        assert_eq!(output.len(), 1);
+        assert!(output[0].starts_with("info string "));
+        assert_eq!(uci.position.to_fen(), Position::starting().to_fen());
+    }
+
+    #[test]
+    fn go_depth_reports_each_depth_then_the_best_move() {
+        let (mut uci, receiver) = uci();
+        let output = run(
+            &mut uci,
+            &receiver,
+            &[
+                "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+                "go depth 2",
+            ],
+        );
+        assert!(output[0].starts_with("info depth 1 score mate 1 "));
+        assert_eq!(output.last().unwrap(), "bestmove a1a8");
+    }
+
+    #[test]
+    fn infinite_search_answers_once_stopped() {
+        let (mut uci, receiver) = uci();
+        assert!(uci.handle("go infinite"));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(receiver
+            .try_iter()
+            .all(|line| !line.starts_with("bestmove")));
+
+        // `stop` waits for the search to answer.
+        assert!(uci.handle("stop"));
+        let output = receiver.try_iter().collect::<Vec<_>>();
+        let best_move = output.last().unwrap().strip_prefix("bestmove ").unwrap();
+        let mv = uci.position.parse_move(best_move).unwrap();
+        assert!(Position::starting().legal_moves().contains(&mv));
+    }
+
+    #[test]
+    fn options_and_unknown_commands() {
+        let (mut uci, receiver) = uci();
+        let output = run(
+            &mut uci,
+            &receiver,
+            &[
+                "setoption name Difficulty value beginner",
+                "setoption name Hash value 16",
+                "frobnicate",
+            ],
+        );
+        assert_eq!(uci.difficulty, Difficulty::Beginner);
+        assert_eq!(
+            output,
+            [
+                "info string Unknown option `Hash`",
+                "info string Unknown command `frobnicate`"
+            ]
+        );
+        assert!(!uci.handle("quit"));
+    }
+}
@@ -0,0 +1,262 @@
+//! Taking moves back and playing them again.
+
+use bevy::prelude::*;
+
+use super::history::HistoryEntry;
+use super::{
+    ActiveTeam, AnimationSettings, AssetLibrary, Chess, ChessBoard, ChessPiece, ChessPieceType,
+    GameConfig, GameHistory, GameOver, HistoryView, PendingPromotion, PieceSelection, PieceTween,
+    Replay,
+};
+use crate::AppState;
+
+/// Request that the most recent move is taken back.
+#[derive(Debug, Event, Clone)]
+pub struct UndoMove;
+
+/// Request that the most recently undone move is played again.
+#[derive(Debug, Event, Clone)]
+pub struct RedoMove;
+
+pub struct TakeBack;
+
+impl TakeBack {
+    /// Undo with "Ctrl+Z" and redo with "Ctrl+Y" or "Ctrl+Shift+Z".
+    pub fn on_keyboard(
+        keyboard_input: Res<ButtonInput<KeyCode>>,
+        view: Option<Res<HistoryView>>,
+        mut undo_writer: EventWriter<UndoMove>,
+        mut redo_writer: EventWriter<RedoMove>,
+    ) {
+        if !keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+            return;
+        }
+        let shift = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+        if view.is_some() && keyboard_input.any_just_pressed([KeyCode::KeyY, KeyCode::KeyZ]) {
+            println!("Moves cannot be undone while viewing an earlier move.");
+        } else if keyboard_input.just_pressed(KeyCode::KeyY)
+            || (shift && keyboard_input.just_pressed(KeyCode::KeyZ))
+        {
+            redo_writer.write(RedoMove);
+        } else if keyboard_input.just_pressed(KeyCode::KeyZ) {
+            undo_writer.write(UndoMove);
+        }
+    }
+
+    /// Take moves back or play them again. Against the computer this goes on
+    /// until it's the player's turn, so the computer doesn't simply play the
+    /// move it just lost again.
+    #[allow(clippy::too_many_arguments)]
+    pub fn on_update(
+        mut commands: Commands,
+        (mut undo_events, mut redo_events): (EventReader<UndoMove>, EventReader<RedoMove>),
+        board: Single<(Entity, &mut ChessBoard, &Transform), Without<ChessPiece>>,
+        mut pieces_query: Query<(&mut ChessPiece, &mut Transform), Without<ChessBoard>>,
+        asset_library: Res<AssetLibrary>,
+        gltf_assets: Res<Assets<Gltf>>,
+        mut history: ResMut<GameHistory>,
+        mut active_team: ResMut<ActiveTeam>,
+        mut selection: ResMut<PieceSelection>,
+        pending_promotion: Res<PendingPromotion>,
+        replay: Option<Res<Replay>>,
+        mut game_over_writer: EventWriter<GameOver>,
+        state: Res<State<AppState>>,
+        mut next_state: ResMut<NextState<AppState>>,
+        animation_settings: Res<AnimationSettings>,
+        config: Res<GameConfig>,
+    ) {
+        let undos = undo_events.read().count();
+        let redos = redo_events.read().count();
+        if undos + redos == 0 {
+            return;
+        }
+
+        if replay.is_some() {
+            println!("Moves cannot be undone while replaying a game.");
+            return;
+        }
+        if pending_promotion.is_pending() {
+            println!("A promotion piece must be chosen first.");
+            return;
+        }
+
+        let (board_entity, mut board, board_transform) = board.into_inner();
+        selection.piece = None;
+
+        let computer_to_move =
+            |board: &ChessBoard| config.computer == Some(board.position.side_to_move());
+
+        let mut undone = 0;
+        while undone < undos || (undone > 0 && computer_to_move(&board)) {
+            let Some(entry) = history.undo() else {
+                if undone < undos {
+                    println!("There are no moves to undo.");
+                }
+                break;
+            };
+
+            TakeBack::unplay_move(
+                &mut commands,
+                board_entity,
+                &mut board,
+                board_transform,
+                &mut pieces_query,
+                &asset_library,
+                &gltf_assets,
+                &entry,
+            );
+            println!("Undid {}", entry.san);
+            undone += 1;
+        }
+
+        let mut redone = 0;
+        while redone < redos || (redone > 0 && computer_to_move(&board)) {
+            let Some(mv) = history.redo_move() else {
+                if redone < redos {
+                    println!("There are no moves to redo.");
+                }
+                break;
+            };
+
+            Chess::play_move(
+                &mut commands,
+                board_entity,
+                &mut board,
+                board_transform,
+                &mut pieces_query,
+                &asset_library,
+                &gltf_assets,
+                &mut history,
+                &animation_settings,
+                mv,
+            );
+            redone += 1;
+
+            if board.position.outcome().is_some() {
+                break;
+            }
+        }
+
+        let is_over = if redone > 0 {
+            Chess::finish_turn(
+                &board.position,
+                &mut active_team,
+                &mut history,
+                &mut game_over_writer,
+                &mut next_state,
+            )
+        } else {
+            active_team.0 = board.position.side_to_move();
+            board.position.outcome().is_some()
+        };
+
+        // Taking back the final move lets the game carry on.
+        if !is_over && *state.get() == AppState::GameOver {
+            next_state.set(AppState::Game);
+        }
+    }
+
+    /// Revert the most recent move on the board's entities and its rules
+    /// model. The move must already have been taken out of the history.
+    #[allow(clippy::too_many_arguments)]
+    fn unplay_move(
+        commands: &mut Commands,
+        board_entity: Entity,
+        board: &mut ChessBoard,
+        board_transform: &Transform,
+        pieces_query: &mut Query<(&mut ChessPiece, &mut Transform), Without<ChessBoard>>,
+        asset_library: &Res<AssetLibrary>,
+        gltf_assets: &Res<Assets<Gltf>>,
+        entry: &HistoryEntry,
+    ) {
+        let mv = board
+            .position
+            .unmake_move()
+            .expect("The history has more moves than the board.");
+        debug_assert_eq!(mv, entry.mv, "The history is out of sync with the board.");
+
+        let moved_entity = board
+            .get_cell(&mv.to)
+            .occupant
+            .expect("The moved piece is missing.");
+        let team = board
+            .position
+            .piece_at(mv.from)
+            .expect("The moved piece is missing from the position.")
+            .team;
+
+        // Promoted pieces turn back into the pawn they came from.
+        if mv.promotion.is_some() {
+            board.remove_piece(moved_entity);
+            commands.entity(moved_entity).despawn();
+
+            let pawn = ChessPiece::spawn(
+                commands,
+                asset_library,
+                gltf_assets,
+                board,
+                board_transform,
+                team,
+                mv.from,
+                ChessPieceType::Pawn,
+                entry.moved.piece,
+            );
+            commands.entity(board_entity).add_child(pawn);
+        } else {
+            let (mut piece, mut transform) = pieces_query
+                .get_mut(moved_entity)
+                .expect("Failed to get moved piece");
+            board.move_piece(
+                moved_entity,
+                &mut piece,
+                &mut transform,
+                mv.from,
+                board_transform,
+            );
+            piece.has_moved = entry.moved.piece;
+            commands.entity(moved_entity).remove::<PieceTween>();
+        }
+
+        // Castling rooks go back to their corner, never having moved.
+        if let Some(side) = board.position.castling_side(&mv) {
+            let squares = side.squares(team);
+            let rook_entity = board
+                .get_cell(&squares.rook_to)
+                .occupant
+                .expect("Castling requires a rook.");
+            let (mut rook, mut rook_transform) = pieces_query
+                .get_mut(rook_entity)
+                .expect("Failed to get castling rook");
+            board.move_piece(
+                rook_entity,
+                &mut rook,
+                &mut rook_transform,
+                squares.rook_from,
+                board_transform,
+            );
+            rook.has_moved = false;
+            commands.entity(rook_entity).remove::<PieceTween>();
+        }
+
+        // Bring back whatever was captured.
+        let captured_square = board
+            .position
+            .en_passant_capture_square(&mv)
+            .unwrap_or(mv.to);
+        if let Some(captured) = board.position.piece_at(captured_square) {
+            let entity = ChessPiece::spawn(
+                commands,
+                asset_library,
+                gltf_assets,
+                board,
+                board_transform,
+                captured.team,
+                captured_square,
+                captured.kind,
+                entry.moved.captured,
+            );
+            commands.entity(board_entity).add_child(entity);
+        }
+    }
+}
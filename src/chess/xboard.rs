@@ -0,0 +1,532 @@
+//! Running the engine under the Chess Engine Communication Protocol (CECP),
+//! as spoken by XBoard, WinBoard and other interfaces of that family.
+//!
+//! Unlike UCI, the engine keeps track of the game itself: the interface sends
+//! the opponent's moves one at a time and the engine answers with its own
+//! whenever it is on move. Searches run on their own thread so commands such
+//! as `?` and `force` are handled while thinking.
+
+use std::io::BufRead;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::engine::{BackgroundSearch, Difficulty, Engine, Score, SearchInfo, SearchLimits};
+use super::uci::{allocate_time, Output, DEFAULT_MOVES_TO_GO};
+use super::{Move, Position, Team};
+
+/// XBoard's convention for reporting mate scores in thinking output.
+const MATE_SCORE: i32 = 100_000;
+
+/// Something for the engine to act on.
+enum Message {
+    /// A line of input from the interface.
+    Command(String),
+
+    /// A search has finished. `search` identifies which one.
+    Searched {
+        search: u64,
+        best_move: Option<Move>,
+    },
+}
+
+/// How much time the engine has, set by `level`, `st` and `time`.
+#[derive(Default)]
+struct Clock {
+    /// Moves per time control, or 0 for the whole game.
+    moves_per_control: u32,
+    increment: Duration,
+
+    /// A fixed time per move from `st`.
+    move_time: Option<Duration>,
+
+    /// The engine's remaining time from `time`.
+    time_left: Option<Duration>,
+
+    /// A depth limit from `sd`.
+    depth: Option<u32>,
+}
+
+struct XBoard {
+    output: Output,
+    engine: Arc<Mutex<Engine>>,
+    position: Position,
+    clock: Clock,
+
+    /// Plies played since `new` or `setboard`, to tell how far into its
+    /// time control the engine is.
+    plies: u32,
+
+    /// The side the engine plays, or `None` in force mode.
+    plays: Option<Team>,
+
+    /// Whether to print thinking output.
+    post: bool,
+
+    messages: Sender<Message>,
+    search: Option<BackgroundSearch>,
+
+    /// How many searches have been started. The running one, if any, is the
+    /// last of them.
+    searches: u64,
+}
+
+/// Speak CECP on stdin and stdout until `quit` or the end of input.
+pub fn run_xboard() {
+    let (messages, receiver) = std::sync::mpsc::channel();
+
+    let input = messages.clone();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if input.send(Message::Command(line)).is_err() {
+                break;
+            }
+        }
+        let _ = input.send(Message::Command("quit".to_string()));
+    });
+
+    XBoard::new(Output::default(), messages).run(receiver);
+}
+
+impl XBoard {
+    fn new(output: Output, messages: Sender<Message>) -> Self {
+        Self {
+            output,
+            engine: Arc::new(Mutex::new(Engine::default())),
+            position: Position::starting(),
+            clock: Clock::default(),
+            plies: 0,
+            plays: Some(Team::Black),
+            post: false,
+            messages,
+            search: None,
+            searches: 0,
+        }
+    }
+
+    fn run(&mut self, receiver: Receiver<Message>) {
+        for message in receiver {
+            if !self.receive(message) {
+                break;
+            }
+        }
+
+        self.stop();
+    }
+
+    /// Act on a message. Returns `false` on `quit`.
+    fn receive(&mut self, message: Message) -> bool {
+        match message {
+            Message::Command(line) => return self.handle(&line),
+            Message::Searched { search, best_move } => {
+                // Searches that were abandoned may still report a move.
+                if self.search.is_some() && search == self.searches {
+                    self.search = None;
+                    self.play(best_move);
+                }
+            }
+        }
+        true
+    }
+
+    /// Act on a command from the interface, which may be a move in force or
+    /// playing mode. Returns `false` on `quit`.
+    fn handle(&mut self, line: &str) -> bool {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let Some((command, args)) = tokens.split_first() else {
+            return true;
+        };
+
+        match *command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer"
+            | "name" | "rating" | "ics" | "otim" => {}
+            "protover" => {
+                self.output.line(
+                    "feature myname=\"Chess\" setboard=1 usermove=1 ping=1 playother=1 \
+                     sigint=0 sigterm=0 colors=0 analyze=0 san=0 done=1",
+                );
+            }
+            "new" => {
+                self.stop();
+                self.engine = Arc::new(Mutex::new(Engine::default()));
+                self.position = Position::starting();
+                self.plies = 0;
+                self.clock = Clock::default();
+                self.plays = Some(Team::Black);
+            }
+            "setboard" => {
+                self.stop();
+                match Position::from_fen(&args.join(" ")) {
+                    Ok(position) => {
+                        self.position = position;
+                        self.plies = 0;
+                    }
+                    Err(e) => self
+                        .output
+                        .line(format_args!("tellusererror Illegal position: {}", e)),
+                }
+            }
+            "force" | "result" => {
+                self.stop();
+                self.plays = None;
+            }
+            "go" => {
+                self.stop();
+                self.plays = Some(self.position.side_to_move());
+                self.think();
+            }
+            "playother" => {
+                self.stop();
+                self.plays = Some(self.position.side_to_move().opponent());
+            }
+            "usermove" => match args.first() {
+                Some(text) => self.user_move(text),
+                None => self.output.line("Error (missing move): usermove"),
+            },
+            // Move now, playing the best move found so far.
+            "?" => {
+                if let Some(search) = &self.search {
+                    search.interrupt();
+                }
+            }
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "level" => self.set_level(args),
+            "st" => match args.first().and_then(|value| value.parse::<u64>().ok()) {
+                Some(seconds) => self.clock.move_time = Some(Duration::from_secs(seconds)),
+                None => self.output.line(format_args!("Error (bad time): {}", line)),
+            },
+            "sd" => match args.first().and_then(|value| value.parse::<u32>().ok()) {
+                Some(depth) => self.clock.depth = Some(depth.max(1)),
+                None => self
+                    .output
+                    .line(format_args!("Error (bad depth): {}", line)),
+            },
+            // Times are given in centiseconds.
+            "time" => match args.first().and_then(|value| value.parse::<u64>().ok()) {
+                Some(time) => self.clock.time_left = Some(Duration::from_millis(time * 10)),
+                None => self.output.line(format_args!("Error (bad time): {}", line)),
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => self
+                .output
+                .line(format_args!("pong {}", args.first().unwrap_or(&""))),
+            "quit" => return false,
+            _ => self
+                .output
+                .line(format_args!("Error (unknown command): {}", command)),
+        }
+
+        true
+    }
+
+    /// Handle `level <moves> <base> <increment>`, where the base time is
+    /// given in minutes or as `minutes:seconds`.
+    fn set_level(&mut self, args: &[&str]) {
+        let [moves, base, increment] = args else {
+            self.output
+                .line(format_args!("Error (bad level): level {}", args.join(" ")));
+            return;
+        };
+
+        let base = match base.split_once(':') {
+            Some((minutes, seconds)) => minutes
+                .parse::<u64>()
+                .ok()
+                .zip(seconds.parse::<u64>().ok())
+                .map(|(minutes, seconds)| minutes * 60 + seconds),
+            None => base.parse::<u64>().ok().map(|minutes| minutes * 60),
+        };
+        let (Ok(moves), Some(base), Ok(increment)) =
+            (moves.parse::<u32>(), base, increment.parse::<f64>())
+        else {
+            self.output
+                .line(format_args!("Error (bad level): level {}", args.join(" ")));
+            return;
+        };
+
+        self.clock = Clock {
+            moves_per_control: moves,
+            increment: Duration::from_secs_f64(increment.max(0.0)),
+            move_time: None,
+            time_left: Some(Duration::from_secs(base)),
+            depth: self.clock.depth,
+        };
+    }
+
+    /// Play the opponent's move and reply if it's then the engine's turn.
+    fn user_move(&mut self, text: &str) {
+        self.stop();
+        match self.position.parse_move(text) {
+            Ok(mv) => {
+                self.position.make_move(mv);
+                self.plies += 1;
+                if !self.report_outcome() && self.plays == Some(self.position.side_to_move()) {
+                    self.think();
+                }
+            }
+            Err(_) => self.output.line(format_args!("Illegal move: {}", text)),
+        }
+    }
+
+    /// Take back `count` moves, leaving the engine to play the same side.
+    fn take_back(&mut self, count: usize) {
+        self.stop();
+        for _ in 0..count {
+            if self.position.unmake_move().is_some() {
+                self.plies = self.plies.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Play the move found by a search.
+    fn play(&mut self, best_move: Option<Move>) {
+        let Some(mv) = best_move else {
+            return;
+        };
+
+        self.output.line(format_args!("move {}", mv));
+        self.position.make_move(mv);
+        self.plies += 1;
+        self.report_outcome();
+    }
+
+    /// Announce the result if the game is over. Returns whether it is.
+    fn report_outcome(&self) -> bool {
+        match self.position.outcome() {
+            Some((result, reason)) => {
+                self.output.line(format_args!("{} {{{}}}", result, reason));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How the engine should search, following the interface's clock.
+    fn limits(&self) -> SearchLimits {
+        let mut limits = Difficulty::Expert.limits();
+
+        if let Some(move_time) = self.clock.move_time {
+            limits.time = Some(move_time);
+        } else if let Some(time_left) = self.clock.time_left {
+            // The engine is on move, so it has made half of the plies since
+            // the game was set up, rounded down.
+            let moves_to_go = match self.clock.moves_per_control {
+                0 => DEFAULT_MOVES_TO_GO,
+                moves => moves - (self.plies / 2) % moves,
+            };
+            limits.time = Some(allocate_time(time_left, self.clock.increment, moves_to_go));
+        }
+
+        if let Some(depth) = self.clock.depth {
+            limits.depth = depth;
+        }
+        limits
+    }
+
+    /// Start searching for the engine's move on another thread.
+    fn think(&mut self) {
+        if self.position.outcome().is_some() {
+            return;
+        }
+
+        let engine = self.engine.clone();
+        let position = self.position.clone();
+        let limits = self.limits();
+        let post = self.post;
+        let output = self.output.clone();
+        let messages = self.messages.clone();
+
+        self.searches += 1;
+        let id = self.searches;
+
+        self.search = Some(BackgroundSearch::spawn(move |stop| {
+            let mut engine = engine.lock().unwrap();
+            let best_move = engine.search(&position, &limits, stop, |info| {
+                if post {
+                    output.line(thinking_output(&position, info));
+                }
+            });
+            let _ = messages.send(Message::Searched {
+                search: id,
+                best_move,
+            });
+        }));
+    }
+
+    /// End any running search without playing its move.
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop();
+        }
+    }
+}
+
+/// Format search progress as `ply score time nodes pv`, with the score in
+/// centipawns, the time in centiseconds and the line in SAN.
+fn thinking_output(position: &Position, info: &SearchInfo) -> String {
+    let score = match info.score {
+        Score::Centipawns(score) => score,
+        Score::Mate(moves) if moves > 0 => MATE_SCORE + moves,
+        Score::Mate(moves) => -MATE_SCORE + moves,
+    };
+
+    let mut position = position.clone();
+    let mut line = Vec::new();
+    for mv in &info.pv {
+        line.push(position.to_san(mv));
+        position.make_move(*mv);
+    }
+
+    format!(
+        "{} {} {} {} {}",
+        info.depth,
+        score,
+        info.time.as_millis() / 10,
+        info.nodes,
+        line.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Harness {
+        xboard: XBoard,
+        messages: Receiver<Message>,
+        output: Receiver<String>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let (output, lines) = Output::channel();
+            let (sender, messages) = std::sync::mpsc::channel();
+            Self {
+                xboard: XBoard::new(output, sender),
+                messages,
+                output: lines,
+            }
+        }
+
+        /// Feed `lines` to the engine and collect what it answers, letting
+        /// any search it starts finish.
+        fn run(&mut self, lines: &[&str]) -> Vec<String> {
+            for line in lines {
+                assert!(self.xboard.handle(line));
+                while self.xboard.search.is_some() {
+                    let message = self
+                        .messages
+                        .recv_timeout(Duration::from_secs(10))
+                        .expect("The search should finish");
+                    assert!(self.xboard.receive(message));
+                }
+            }
+            self.output.try_iter().collect()
+        }
+    }
+
+    #[test]
+    fn features_and_ping() {
+        let mut harness = Harness::new();
+        let output = harness.run(&["xboard", "protover 2", "ping 7"]);
+        assert!(output[0].starts_with("feature myname=\"Chess\" setboard=1 usermove=1"));
+        assert!(output[0].ends_with("done=1"));
+        assert_eq!(output[1], "pong 7");
+    }
+
+    #[test]
+    fn engine_answers_the_users_move() {
+        let mut harness = Harness::new();
+        let output = harness.run(&["new", "sd 1", "usermove e2e4"]);
+        assert_eq!(output.len(), 1);
+
+        let text = output[0].strip_prefix("move ").unwrap();
+        let mut position = Position::starting();
+        position.make_move(position.parse_move("e2e4").unwrap());
+        assert!(position.parse_move(text).is_ok());
+        assert_eq!(harness.xboard.plies, 2);
+    }
+
+    #[test]
+    fn force_mode_takes_moves_without_answering() {
+        let mut harness = Harness::new();
+        let output = harness.run(&["force", "usermove e2e4", "usermove e7e5", "undo"]);
+        assert!(output.is_empty());
+        assert_eq!(
+            harness.xboard.position.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+        assert_eq!(harness.xboard.plies, 1);
+
+        // `go` plays the side to move from then on.
+        let output = harness.run(&["sd 1", "go"]);
+        assert_eq!(output.len(), 1);
+        assert!(output[0].starts_with("move "));
+        assert_eq!(harness.xboard.plays, Some(Team::Black));
+    }
+
+    /// Moves in the time control count from the position set up, not from
+    /// the start of the game it was taken from.
+    #[test]
+    fn time_control_starts_at_setboard() {
+        let mut harness = Harness::new();
+        harness.run(&[
+            "force",
+            "level 40 5 0",
+            "setboard r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 30",
+        ]);
+        let period =
+            |moves_to_go| allocate_time(Duration::from_secs(300), Duration::ZERO, moves_to_go);
+        assert_eq!(harness.xboard.limits().time, Some(period(40)));
+
+        harness.run(&["usermove f1c4", "usermove g8f6"]);
+        assert_eq!(harness.xboard.limits().time, Some(period(39)));
+
+        harness.run(&["remove"]);
+        assert_eq!(harness.xboard.limits().time, Some(period(40)));
+    }
+
+    #[test]
+    fn thinking_output_and_result() {
+        let mut harness = Harness::new();
+        let output = harness.run(&[
+            "setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            "post",
+            "sd 2",
+            "go",
+        ]);
+        let first = output[0].split_whitespace().collect::<Vec<_>>();
+        assert_eq!(first[..2], ["1", "100001"]);
+        assert_eq!(first.last(), Some(&"Ra8#"));
+        assert_eq!(output[output.len() - 2..], ["move a1a8", "1-0 {checkmate}"]);
+    }
+
+    #[test]
+    fn bad_commands_are_reported() {
+        let mut harness = Harness::new();
+        let output = harness.run(&[
+            "setboard 9/8/8/8/8/8/8/8 w - - 0 1",
+            "force",
+            "usermove e2e5",
+            "level 40",
+            "sd deep",
+            "frobnicate",
+        ]);
+        assert!(output[0].starts_with("tellusererror Illegal position: "));
+        assert_eq!(
+            output[1..],
+            [
+                "Illegal move: e2e5",
+                "Error (bad level): level 40",
+                "Error (bad depth): sd deep",
+                "Error (unknown command): frobnicate",
+            ]
+        );
+        assert!(!harness.xboard.handle("quit"));
+    }
+}
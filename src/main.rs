@@ -16,39 +16,155 @@ pub enum AppState {
     Menu,
     GameLoading,
     Game,
+    GameOver,
     Shutdown,
 }
 
-fn on_startup(mut next_state: ResMut<NextState<AppState>>) {
-    next_state.set(AppState::GameLoading);
+fn on_startup(replay: Option<Res<Replay>>, mut next_state: ResMut<NextState<AppState>>) {
+    // Replays don't need a new game set up.
+    if replay.is_some() {
+        next_state.set(AppState::GameLoading);
+    } else {
+        next_state.set(AppState::MenuLoading);
+    }
 }
 
 fn on_shutdown(mut exit: EventWriter<AppExit>) {
     exit.write(AppExit::Success);
 }
 
+/// Return the value following `flag` on the command line, if present.
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == flag)?;
+    args.next()
+}
+
+/// Print the perft count of each move and their total, the way other engines
+/// do so the output can be compared.
+fn run_perft(position: &Position, depth: u32) {
+    let start = std::time::Instant::now();
+    let mut divide = position
+        .divide(depth)
+        .into_iter()
+        .map(|(mv, nodes)| (mv.to_string(), nodes))
+        .collect::<Vec<_>>();
+    divide.sort();
+
+    for (mv, nodes) in &divide {
+        println!("{}: {}", mv, nodes);
+    }
+    println!();
+    println!(
+        "Nodes searched: {}",
+        divide.iter().map(|(_, nodes)| nodes).sum::<u64>()
+    );
+    println!("Time: {} ms", start.elapsed().as_millis());
+}
+
 fn main() {
+    // Act as a UCI or XBoard engine for other programs instead of opening a window.
+    if std::env::args().any(|arg| arg == "--uci") {
+        run_uci();
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--xboard") {
+        run_xboard();
+        return;
+    }
+
+    // Games may be set up from any position, e.g. `--fen "8/8/8/4k3/8/8/4P3/4K3 w - - 0 1"`.
+    let starting_position = match arg_value("--fen") {
+        Some(fen) => Position::from_fen(&fen).unwrap_or_else(|e| panic!("Invalid `--fen`: {}", e)),
+        None => Position::starting(),
+    };
+
+    // Check move generation with `--perft 5`, printing the count below each move.
+    if let Some(depth) = arg_value("--perft") {
+        let depth = depth
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid `--perft`: {}", e));
+        run_perft(&starting_position, depth);
+        return;
+    }
+
+    // Recorded games can be replayed with `--pgn games.pgn --game 3`.
+    let replay = arg_value("--pgn").map(|path| {
+        let number = arg_value("--game").map_or(1, |number| {
+            number
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid `--game`: {}", number))
+        });
+        Replay::load(&path, number).unwrap_or_else(|e| panic!("Invalid `--pgn`: {}", e))
+    });
+    let starting_position = match &replay {
+        Some(replay) => replay.starting_position().clone(),
+        None => starting_position,
+    };
+
+    // Preselect options on the new game screen, e.g. `--computer black --difficulty expert`.
+    let computer = arg_value("--computer").map(|team| match team.as_str() {
+        "white" => Team::White,
+        "black" => Team::Black,
+        _ => panic!("Invalid `--computer`: {}", team),
+    });
+    let difficulty = arg_value("--difficulty").map_or(Difficulty::default(), |difficulty| {
+        difficulty
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid `--difficulty`: {}", difficulty))
+    });
+
+    // Speed animations up or slow them down with `--animation-speed 2`, or turn them off with 0.
+    let animation_speed = arg_value("--animation-speed").map_or(1.0, |speed| {
+        speed
+            .parse::<f32>()
+            .ok()
+            .filter(|speed| *speed >= 0.0)
+            .unwrap_or_else(|| panic!("Invalid `--animation-speed`: {}", speed))
+    });
+
     let loader_path = assets::LOADER_PATH.to_string_lossy().to_string();
-    App::new()
-        .add_plugins(
-            DefaultPlugins
-                .set(AssetPlugin {
-                    file_path: loader_path.clone(),
-                    processed_file_path: loader_path,
-                    ..Default::default()
-                })
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        title: "Chess".into(),
-                        ..default()
-                    }),
+    let book = arg_value("--book");
+    let mut app = App::new();
+    // The book may be kept anywhere, so its folder needs an asset source.
+    if let Some(book) = &book {
+        OpeningBook::register_source(&mut app, book);
+    }
+    app.add_plugins(
+        DefaultPlugins
+            .set(AssetPlugin {
+                file_path: loader_path.clone(),
+                processed_file_path: loader_path,
+                ..Default::default()
+            })
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: "Chess".into(),
                     ..default()
                 }),
-        )
-        .init_state::<AppState>()
-        .add_systems(Startup, on_startup)
-        .add_systems(OnEnter(AppState::Shutdown), on_shutdown)
-        .add_plugins(MenuPlugin)
-        .add_plugins(ChessPlugin)
-        .run();
+                ..default()
+            }),
+    )
+    .init_state::<AppState>()
+    .insert_resource(StartingPosition(starting_position))
+    .insert_resource(GameConfig {
+        computer,
+        difficulty,
+        engine: arg_value("--engine"),
+        book,
+    })
+    .insert_resource(AnimationSettings {
+        speed: animation_speed,
+        skip: false,
+    })
+    .add_systems(Startup, on_startup)
+    .add_systems(OnEnter(AppState::Shutdown), on_shutdown)
+    .add_plugins(MenuPlugin)
+    .add_plugins(ChessPlugin);
+
+    if let Some(replay) = replay {
+        app.insert_resource(replay);
+    }
+
+    app.run();
 }
@@ -1,19 +1,27 @@
 use bevy::log::debug;
 use bevy::prelude::*;
+use strum::IntoEnumIterator;
 
+use crate::chess::{Difficulty, GameConfig, Team};
 use crate::AppState;
 
 #[derive(Clone)]
 enum Action {
     Exit,
     Play,
+
+    /// Choose who the computer plays, if anyone.
+    Opponent(Option<Team>),
+    Difficulty(Difficulty),
 }
 
-impl From<&Action> for AppState {
-    fn from(value: &Action) -> Self {
-        match value {
-            Action::Exit => AppState::Shutdown,
-            Action::Play => AppState::GameLoading,
+impl Action {
+    /// Whether the button shows the option currently chosen for the new game.
+    fn is_selected(&self, config: &GameConfig) -> bool {
+        match self {
+            Action::Exit | Action::Play => false,
+            Action::Opponent(computer) => *computer == config.computer,
+            Action::Difficulty(difficulty) => *difficulty == config.difficulty,
         }
     }
 }
@@ -22,6 +30,52 @@ impl From<&Action> for AppState {
 struct MenuButton(Action);
 
 const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+const SELECTED_BUTTON: Color = Color::srgb(0.25, 0.45, 0.25);
+
+/// A menu button with a text label.
+fn menu_button(action: Action, label: String, width: f32) -> impl Bundle {
+    (
+        Button,
+        MenuButton(action),
+        Node {
+            width: Val::Px(width),
+            height: Val::Px(65.),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        children![(
+            Text::new(label),
+            TextFont {
+                font_size: 24.0,
+                ..default()
+            },
+            TextColor(Color::srgb(0.9, 0.9, 0.9)),
+        )],
+    )
+}
+
+/// A heading above a row of options.
+fn menu_heading(label: &str) -> impl Bundle {
+    (
+        Text::new(label),
+        TextFont {
+            font_size: 28.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+    )
+}
+
+fn menu_row() -> Node {
+    Node {
+        column_gap: Val::Px(10.),
+        ..default()
+    }
+}
 
 #[derive(Resource)]
 pub struct Menu(Entity);
@@ -29,62 +83,52 @@ pub struct Menu(Entity);
 impl Menu {
     fn on_loading(mut commands: Commands, mut next_state: ResMut<NextState<AppState>>) {
         let root = commands
-            .spawn((
-                Node {
-                    // center button
-                    width: Val::Percent(100.),
-                    height: Val::Percent(100.),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    ..default()
-                },
-                children![
-                    (
-                        Button,
-                        MenuButton(Action::Play),
-                        Node {
-                            width: Val::Px(150.),
-                            height: Val::Px(65.),
-                            // horizontally center child text
-                            justify_content: JustifyContent::Center,
-                            // vertically center child text
-                            align_items: AlignItems::Center,
-                            ..default()
-                        },
-                        BackgroundColor(NORMAL_BUTTON),
-                        children![(
-                            Text::new("Play"),
-                            TextFont {
-                                font_size: 33.0,
-                                ..default()
-                            },
-                            TextColor(Color::srgb(0.9, 0.9, 0.9)),
-                        )],
-                    ),
-                    (
-                        Button,
-                        MenuButton(Action::Exit),
-                        Node {
-                            width: Val::Px(150.),
-                            height: Val::Px(65.),
-                            // horizontally center child text
-                            justify_content: JustifyContent::Center,
-                            // vertically center child text
-                            align_items: AlignItems::Center,
-                            ..default()
-                        },
-                        BackgroundColor(NORMAL_BUTTON),
-                        children![(
-                            Text::new("Exit"),
-                            TextFont {
-                                font_size: 33.0,
-                                ..default()
-                            },
-                            TextColor(Color::srgb(0.9, 0.9, 0.9)),
-                        )],
-                    )
-                ],
-            ))
+            .spawn(Node {
+                // center the menu
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(15.),
+                ..default()
+            })
+            .with_children(|parent| {
+                parent.spawn(menu_heading("Opponent"));
+                parent.spawn(menu_row()).with_children(|row| {
+                    row.spawn(menu_button(
+                        Action::Opponent(None),
+                        "Human".to_string(),
+                        200.,
+                    ));
+                    row.spawn(menu_button(
+                        Action::Opponent(Some(Team::Black)),
+                        "Computer (Black)".to_string(),
+                        200.,
+                    ));
+                    row.spawn(menu_button(
+                        Action::Opponent(Some(Team::White)),
+                        "Computer (White)".to_string(),
+                        200.,
+                    ));
+                });
+
+                parent.spawn(menu_heading("Difficulty"));
+                parent.spawn(menu_row()).with_children(|row| {
+                    for difficulty in Difficulty::iter() {
+                        row.spawn(menu_button(
+                            Action::Difficulty(difficulty),
+                            difficulty.to_string(),
+                            150.,
+                        ));
+                    }
+                });
+
+                parent.spawn(menu_row()).with_children(|row| {
+                    row.spawn(menu_button(Action::Play, "Play".to_string(), 150.));
+                    row.spawn(menu_button(Action::Exit, "Exit".to_string(), 150.));
+                });
+            })
             .id();
 
         // Track the menu items
@@ -98,22 +142,48 @@ impl Menu {
     }
 
     /// Handle button presses and perform their associated actions.
+    #[allow(clippy::type_complexity)]
     fn on_update(
         mut next_state: ResMut<NextState<AppState>>,
+        mut config: ResMut<GameConfig>,
         mut interaction_query: Query<
             (&Interaction, &MenuButton),
             (Changed<Interaction>, With<MenuButton>),
         >,
     ) {
         for (interaction, action) in &mut interaction_query {
-            match interaction {
-                Interaction::Pressed => {
-                    let new_state = AppState::from(&action.0);
-                    debug!("NEW STATE: {:?}", new_state);
-                    next_state.set(new_state);
-                }
-                _ => {}
+            if *interaction != Interaction::Pressed {
+                continue;
             }
+
+            let new_state = match &action.0 {
+                Action::Exit => AppState::Shutdown,
+                Action::Play => AppState::GameLoading,
+                Action::Opponent(computer) => {
+                    config.computer = *computer;
+                    continue;
+                }
+                Action::Difficulty(difficulty) => {
+                    config.difficulty = *difficulty;
+                    continue;
+                }
+            };
+            debug!("NEW STATE: {:?}", new_state);
+            next_state.set(new_state);
+        }
+    }
+
+    /// Highlight the options chosen for the new game.
+    fn update_selection(
+        config: Res<GameConfig>,
+        mut button_query: Query<(&MenuButton, &mut BackgroundColor)>,
+    ) {
+        for (button, mut background) in &mut button_query {
+            background.0 = if button.0.is_selected(&config) {
+                SELECTED_BUTTON
+            } else {
+                NORMAL_BUTTON
+            };
         }
     }
 
@@ -136,7 +206,10 @@ pub struct MenuPlugin;
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(AppState::MenuLoading), Menu::on_loading)
-            .add_systems(Update, Menu::on_update.run_if(in_state(AppState::Menu)))
+            .add_systems(
+                Update,
+                (Menu::on_update, Menu::update_selection).run_if(in_state(AppState::Menu)),
+            )
             .add_systems(OnExit(AppState::Menu), Menu::on_exit);
     }
 }